@@ -6,10 +6,21 @@ use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::{TcpStream, UdpSocket};
 
 #[cfg(target_family = "unix")]
-use tokio::net::UnixStream;
+use tokio::net::{UnixDatagram, UnixStream};
 
 use crate::address::NetLocation;
 
+/// Metadata learned about a connection that would otherwise be discarded once the underlying
+/// stream is boxed into a `Box<dyn AsyncStream>`, so that later stages (e.g. `client_proxy_selector`)
+/// can route or deny by peer address/ALPN/SNI. Mirrors tonic's `Connected`/`connect_info` split
+/// and tls-api's `get_alpn_protocol`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnInfo {
+    pub(crate) peer_addr: Option<SocketAddr>,
+    pub(crate) alpn_protocol: Option<Vec<u8>>,
+    pub(crate) server_name: Option<String>,
+}
+
 pub(crate) trait AsyncPing {
     fn supports_ping(&self) -> bool;
 
@@ -119,7 +130,14 @@ impl AsyncShutdownMessage for UdpSocket {
     }
 }
 
-pub(crate) trait AsyncStream: AsyncRead + AsyncWrite + AsyncPing + Unpin + Send {}
+pub(crate) trait AsyncStream: AsyncRead + AsyncWrite + AsyncPing + Unpin + Send {
+    // Default to no metadata; plain TCP streams override only `peer_addr` (a Unix peer has no
+    // `SocketAddr`, so `UnixStream` keeps this default), and the TLS wrappers below fill in
+    // ALPN/SNI from the completed handshake.
+    fn connect_info(&self) -> ConnInfo {
+        ConnInfo::default()
+    }
+}
 
 pub(crate) trait AsyncMessageStream:
     AsyncReadMessage
@@ -168,7 +186,14 @@ impl AsyncPing for TcpStream {
     }
 }
 
-impl AsyncStream for TcpStream {}
+impl AsyncStream for TcpStream {
+    fn connect_info(&self) -> ConnInfo {
+        ConnInfo {
+            peer_addr: self.peer_addr().ok(),
+            ..Default::default()
+        }
+    }
+}
 
 #[cfg(target_family = "unix")]
 impl AsyncPing for UnixStream {
@@ -184,6 +209,63 @@ impl AsyncPing for UnixStream {
 #[cfg(target_family = "unix")]
 impl AsyncStream for UnixStream {}
 
+#[cfg(target_family = "unix")]
+impl AsyncReadMessage for UnixDatagram {
+    fn poll_read_message(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.poll_recv(cx, buf)
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl AsyncWriteMessage for UnixDatagram {
+    fn poll_write_message(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<()>> {
+        // TODO: send back an error if the whole buf.len() wasn't sent?
+        self.poll_send(cx, buf).map(|result| result.map(|_| ()))
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl AsyncFlushMessage for UnixDatagram {
+    fn poll_flush_message(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl AsyncShutdownMessage for UnixDatagram {
+    fn poll_shutdown_message(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl AsyncPing for UnixDatagram {
+    fn supports_ping(&self) -> bool {
+        false
+    }
+
+    fn poll_write_ping(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<bool>> {
+        unimplemented!();
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl AsyncMessageStream for UnixDatagram {}
+
 impl AsyncPing for UdpSocket {
     fn supports_ping(&self) -> bool {
         false
@@ -210,7 +292,17 @@ where
     }
 }
 
-impl<AS> AsyncStream for tokio_rustls::client::TlsStream<AS> where AS: AsyncStream {}
+impl<AS> AsyncStream for tokio_rustls::client::TlsStream<AS>
+where
+    AS: AsyncStream,
+{
+    fn connect_info(&self) -> ConnInfo {
+        let (io, session) = self.get_ref();
+        let mut conn_info = io.connect_info();
+        conn_info.alpn_protocol = session.alpn_protocol().map(|p| p.to_vec());
+        conn_info
+    }
+}
 
 impl<AS> AsyncPing for tokio_rustls::server::TlsStream<AS>
 where
@@ -226,7 +318,18 @@ where
     }
 }
 
-impl<AS> AsyncStream for tokio_rustls::server::TlsStream<AS> where AS: AsyncStream {}
+impl<AS> AsyncStream for tokio_rustls::server::TlsStream<AS>
+where
+    AS: AsyncStream,
+{
+    fn connect_info(&self) -> ConnInfo {
+        let (io, session) = self.get_ref();
+        let mut conn_info = io.connect_info();
+        conn_info.alpn_protocol = session.alpn_protocol().map(|p| p.to_vec());
+        conn_info.server_name = session.server_name().map(|s| s.to_string());
+        conn_info
+    }
+}
 
 // pattern copied from deref_async_read macro: https://docs.rs/tokio/latest/src/tokio/io/async_read.rs.html#60
 impl<T: ?Sized + AsyncPing + Unpin> AsyncPing for Box<T> {
@@ -415,8 +518,16 @@ impl<T: ?Sized + AsyncWriteSourcedMessage + Unpin> AsyncWriteSourcedMessage for
     }
 }
 
-impl<T: ?Sized + AsyncStream + Unpin> AsyncStream for Box<T> {}
-impl<T: ?Sized + AsyncStream + Unpin> AsyncStream for &mut T {}
+impl<T: ?Sized + AsyncStream + Unpin> AsyncStream for Box<T> {
+    fn connect_info(&self) -> ConnInfo {
+        (**self).connect_info()
+    }
+}
+impl<T: ?Sized + AsyncStream + Unpin> AsyncStream for &mut T {
+    fn connect_info(&self) -> ConnInfo {
+        (**self).connect_info()
+    }
+}
 
 impl<T: ?Sized + AsyncMessageStream + Unpin> AsyncMessageStream for Box<T> {}
 impl<T: ?Sized + AsyncMessageStream + Unpin> AsyncMessageStream for &mut T {}
@@ -0,0 +1,151 @@
+use std::io;
+use std::task::{Context, Poll};
+
+use tokio::net::TcpStream;
+
+#[cfg(target_family = "unix")]
+use tokio::net::UnixStream;
+
+/// Readiness-oriented counterpart to the poll-based `AsyncRead`/`AsyncWrite` traits, modeled on
+/// sqlx's `Socket`. The message protocols (vmess/vless/trojan framing, UDP relays) otherwise pay
+/// one syscall per frame; this trait lets `BufferedSocket` coalesce several frames into a single
+/// `try_write` once the socket reports writable.
+pub(crate) trait Socket: Send {
+    fn try_read(&self, buf: &mut [u8]) -> io::Result<usize>;
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize>;
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+impl Socket for TcpStream {
+    fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        TcpStream::try_read(self, buf)
+    }
+
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        TcpStream::try_write(self, buf)
+    }
+
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_write_ready(cx)
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl Socket for UnixStream {
+    fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        UnixStream::try_read(self, buf)
+    }
+
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        UnixStream::try_write(self, buf)
+    }
+
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_read_ready(cx)
+    }
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_write_ready(cx)
+    }
+}
+
+/// Wraps a [`Socket`] with a write-accumulation buffer so callers can push several frames before
+/// a single flush issues one `try_write`, rather than one `write`/`send` syscall per frame.
+pub(crate) struct BufferedSocket<S: Socket> {
+    socket: S,
+    write_buf: Vec<u8>,
+}
+
+impl<S: Socket> BufferedSocket<S> {
+    pub(crate) fn new(socket: S) -> Self {
+        Self {
+            socket,
+            write_buf: Vec::new(),
+        }
+    }
+
+    /// Queues `data` to be sent on the next flush. Does not touch the socket.
+    pub(crate) fn queue_write(&mut self, data: &[u8]) {
+        self.write_buf.extend_from_slice(data);
+    }
+
+    pub(crate) fn has_queued_writes(&self) -> bool {
+        !self.write_buf.is_empty()
+    }
+
+    /// Flushes the accumulated buffer, waiting for writability and retrying short writes, using a
+    /// single `try_write` per readiness event rather than one per queued frame.
+    pub(crate) async fn flush(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        while written < self.write_buf.len() {
+            self.socket.poll_write_ready_async().await?;
+            match self.socket.try_write(&self.write_buf[written..]) {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        self.write_buf.clear();
+        Ok(())
+    }
+
+    /// Reads directly off the socket once it reports readable, bypassing the write buffer.
+    pub(crate) async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            self.socket.poll_read_ready_async().await?;
+            match self.socket.try_read(buf) {
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// Small async adapters over the poll-based readiness methods so `BufferedSocket` can `.await`
+// them instead of hand-rolling a `poll_fn` at every call site.
+trait SocketReadinessExt: Socket {
+    fn poll_read_ready_async(&self) -> ReadyFuture<'_, Self>
+    where
+        Self: Sized,
+    {
+        ReadyFuture {
+            socket: self,
+            is_write: false,
+        }
+    }
+
+    fn poll_write_ready_async(&self) -> ReadyFuture<'_, Self>
+    where
+        Self: Sized,
+    {
+        ReadyFuture {
+            socket: self,
+            is_write: true,
+        }
+    }
+}
+
+impl<S: Socket> SocketReadinessExt for S {}
+
+struct ReadyFuture<'a, S: Socket> {
+    socket: &'a S,
+    is_write: bool,
+}
+
+impl<S: Socket> std::future::Future for ReadyFuture<'_, S> {
+    type Output = io::Result<()>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.is_write {
+            self.socket.poll_write_ready(cx)
+        } else {
+            self.socket.poll_read_ready(cx)
+        }
+    }
+}
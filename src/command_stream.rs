@@ -0,0 +1,119 @@
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+
+use crate::async_stream::{AsyncPing, AsyncStream};
+
+/// How long a child is given to exit on its own (stdin closed) before `poll_shutdown` escalates
+/// to `start_kill`.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+/// An `AsyncStream` backed by a spawned subprocess's stdin/stdout, so a connection can be piped
+/// through an external obfuscator/pluggable transport (obfs4, cloak, stunnel, ...) without it
+/// needing to be compiled into `shoes` itself. Modeled on the `Stream` sum-type pattern used by
+/// thrussh, where a `Tcp(TcpStream)` arm sits alongside a process-backed arm.
+#[derive(Debug)]
+pub(crate) struct CommandStream {
+    // `None` once ownership has been handed to the grace-period/kill task spawned from
+    // `poll_shutdown`, or to `Drop` on a shutdown-less teardown.
+    child: Option<Child>,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl CommandStream {
+    pub(crate) fn spawn(program: &str, args: &[String]) -> std::io::Result<Self> {
+        let mut child = tokio::process::Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+
+        let stdin = child.stdin.take().ok_or_else(|| {
+            std::io::Error::other(format!("failed to capture stdin for command '{program}'"))
+        })?;
+        let stdout = child.stdout.take().ok_or_else(|| {
+            std::io::Error::other(format!("failed to capture stdout for command '{program}'"))
+        })?;
+
+        Ok(Self {
+            child: Some(child),
+            stdin,
+            stdout,
+        })
+    }
+}
+
+impl AsyncRead for CommandStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CommandStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stdin).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let result = Pin::new(&mut self.stdin).poll_shutdown(cx);
+        // Once stdin has closed, hand the child off to a detached task that gives it
+        // `SHUTDOWN_GRACE_PERIOD` to exit on its own (closing stdin is usually enough for a
+        // well-behaved pluggable transport) before escalating to `start_kill`, rather than
+        // killing it outright in the same call. `child.take()` makes this a one-shot: a second
+        // `poll_shutdown` call (or a later `Drop`) finds `None` and does nothing further.
+        if result.is_ready() {
+            if let Some(mut child) = self.child.take() {
+                tokio::spawn(async move {
+                    if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, child.wait())
+                        .await
+                        .is_err()
+                    {
+                        let _ = child.start_kill();
+                    }
+                });
+            }
+        }
+        result
+    }
+}
+
+impl Drop for CommandStream {
+    fn drop(&mut self) {
+        // `poll_shutdown` is the clean path (see above); this only fires when a `CommandStream`
+        // is dropped without going through it (e.g. a connection error path or a panic unwind),
+        // so there's no grace period left to give the child - just make sure it doesn't leak.
+        if let Some(mut child) = self.child.take() {
+            let _ = child.start_kill();
+        }
+    }
+}
+
+impl AsyncPing for CommandStream {
+    fn supports_ping(&self) -> bool {
+        false
+    }
+
+    fn poll_write_ping(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<bool>> {
+        unimplemented!();
+    }
+}
+
+impl AsyncStream for CommandStream {}
@@ -1,7 +1,9 @@
 mod address;
 mod async_stream;
 mod buf_reader;
+mod buffered_socket;
 mod client_proxy_selector;
+mod command_stream;
 mod copy_bidirectional;
 mod copy_bidirectional_message;
 mod copy_multidirectional_message;
@@ -16,6 +18,7 @@ mod rustls_util;
 mod salt_checker;
 mod shadow_tls;
 mod shadowsocks;
+mod shutdown;
 mod snell;
 mod socket_util;
 mod socks_handler;
@@ -28,6 +31,7 @@ mod trojan_handler;
 mod tuic_server;
 mod udp_message_stream;
 mod udp_multi_message_stream;
+mod udp_server;
 mod util;
 mod vless_handler;
 mod vless_message_stream;
@@ -42,13 +46,21 @@ pub use config::ServerConfig;
 use tokio::task::JoinHandle;
 
 use crate::{
-    config::Transport, quic_server::start_quic_servers, tcp::tcp_server::start_tcp_servers,
+    config::Transport, quic_server::start_quic_servers, shutdown::ShutdownController,
+    tcp::tcp_server::start_tcp_servers, tcp::unix_tcp_server::start_unix_servers,
+    udp_server::start_udp_servers,
 };
 
 pub async fn start_servers(config: ServerConfig) -> std::io::Result<Vec<JoinHandle<()>>> {
+    // callers of this one-shot API don't get to observe or trigger shutdown, so the controller
+    // here never has `begin_shutdown` called on it; `main()`'s reload loop owns its own
+    // `ShutdownController` per running server definition instead, see `shutdown`.
+    let shutdown = ShutdownController::new();
     let mut join_handles = Vec::with_capacity(3);
 
     match config.transport {
+        // TODO: thread `shutdown.token()` through once `start_tcp_servers`/`start_quic_servers`
+        // select on it the same way `start_unix_servers`/`start_udp_servers` do below.
         Transport::Tcp => match start_tcp_servers(config.clone()).await {
             Ok(handles) => {
                 join_handles.extend(handles);
@@ -71,7 +83,32 @@ pub async fn start_servers(config: ServerConfig) -> std::io::Result<Vec<JoinHand
                 return Err(e);
             }
         },
-        Transport::Udp => todo!(),
+        Transport::Unix => match start_unix_servers(
+            config.bind_location.unix_path()?,
+            config.clone(),
+            shutdown.token(),
+        ) {
+            Ok(handles) => {
+                join_handles.extend(handles);
+            }
+            Err(e) => {
+                for join_handle in join_handles {
+                    join_handle.abort();
+                }
+                return Err(e);
+            }
+        },
+        Transport::Udp => match start_udp_servers(config.clone(), shutdown.token()).await {
+            Ok(handles) => {
+                join_handles.extend(handles);
+            }
+            Err(e) => {
+                for join_handle in join_handles {
+                    join_handle.abort();
+                }
+                return Err(e);
+            }
+        },
     }
 
     if join_handles.is_empty() {
@@ -81,5 +118,17 @@ pub async fn start_servers(config: ServerConfig) -> std::io::Result<Vec<JoinHand
         )));
     }
 
+    // `shutdown` must outlive this function: dropping it here would close the watch channel,
+    // which every `ShutdownToken::wait_for_shutdown` treats identically to an explicit shutdown
+    // signal, so the accept loops just handed out tokens would stop right after starting. Since
+    // this one-shot API never calls `begin_shutdown` either, the controller simply has no further
+    // use once its tokens are handed out; rather than leaking it with `mem::forget` (which leaks
+    // it once per call for the life of the process), park it in a task of its own so it lives and
+    // dies alongside the other `join_handles` this call returns.
+    join_handles.push(tokio::spawn(async move {
+        let _shutdown = shutdown;
+        std::future::pending::<()>().await;
+    }));
+
     Ok(join_handles)
 }
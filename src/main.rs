@@ -1,7 +1,9 @@
 mod address;
 mod async_stream;
 mod buf_reader;
+mod buffered_socket;
 mod client_proxy_selector;
+mod command_stream;
 mod config;
 mod copy_bidirectional;
 mod copy_bidirectional_message;
@@ -18,6 +20,7 @@ mod rustls_util;
 mod salt_checker;
 mod shadow_tls;
 mod shadowsocks;
+mod shutdown;
 mod snell;
 mod socket_util;
 mod socks_handler;
@@ -30,6 +33,7 @@ mod trojan_handler;
 mod tuic_server;
 mod udp_message_stream;
 mod udp_multi_message_stream;
+mod udp_server;
 mod util;
 mod vless_handler;
 mod vless_message_stream;
@@ -44,6 +48,9 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -57,12 +64,79 @@ use tokio::task::JoinHandle;
 
 use crate::config::{ServerConfig, Transport};
 use crate::quic_server::start_quic_servers;
+use crate::shutdown::{ShutdownController, ShutdownToken};
+use crate::tcp::unix_tcp_server::start_unix_servers;
 use crate::thread_util::set_num_threads;
+use crate::udp_server::start_udp_servers;
 use tcp::*;
 
+/// How long a config reload or SIGINT/SIGTERM waits for in-flight connections to finish on their
+/// own before the previous generation's servers are forcibly aborted.
+const SHUTDOWN_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(30);
+
 #[derive(Debug)]
 struct ConfigChanged;
 
+/// One running server definition, keyed by its bind location in `main`'s reload loop so that a
+/// config change only tears down and restarts the definitions that actually changed, instead of
+/// every server in the file.
+struct RunningServer {
+    join_handles: Vec<JoinHandle<()>>,
+    shutdown_controller: ShutdownController,
+    /// Hash of the server's `{config:?}` output, used to tell whether this definition changed
+    /// between reloads without requiring `ServerConfig` itself to implement `PartialEq`/`Hash`.
+    fingerprint: u64,
+}
+
+/// Fingerprints a config via its `Debug` output so reloads can detect whether a server definition
+/// actually changed, independent of whatever fields `ServerConfig` happens to have.
+fn config_fingerprint(config: &ServerConfig) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{config:?}").hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Begins shutdown, drains, and aborts one generation's server. Used to fully retire a changed
+/// server's old listener *before* its replacement binds the same address, since otherwise the
+/// replacement's `bind()` would race the still-live old listener and fail with `AddrInUse`.
+async fn retire_server(server: RunningServer) {
+    server.shutdown_controller.begin_shutdown();
+    server.shutdown_controller.drain(SHUTDOWN_GRACE_PERIOD).await;
+
+    // Accept loops that select on shutdown will already have exited on their own by now; this
+    // just forces out anything left (transports that don't yet watch `shutdown`, or connections
+    // still outstanding after the grace period).
+    for join_handle in server.join_handles {
+        join_handle.abort();
+    }
+}
+
+/// Resolves once the process receives Ctrl+C or (on unix) SIGTERM, so the reload loop can select
+/// on it alongside `config_rx` and shut down the same way a config reload retires a generation of
+/// servers, rather than exiting out from under in-flight connections.
+async fn wait_for_shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install CTRL+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
 fn start_notify_thread(
     config_paths: Vec<PathBuf>,
 ) -> (RecommendedWatcher, UnboundedReceiver<ConfigChanged>) {
@@ -87,10 +161,15 @@ fn start_notify_thread(
     (watcher, rx)
 }
 
-async fn start_servers(config: ServerConfig) -> std::io::Result<Vec<JoinHandle<()>>> {
+async fn start_servers(
+    config: ServerConfig,
+    shutdown: ShutdownToken,
+) -> std::io::Result<Vec<JoinHandle<()>>> {
     let mut join_handles = Vec::with_capacity(3);
 
     match config.transport {
+        // TODO: thread `shutdown` through once `start_tcp_servers`/`start_quic_servers` select on
+        // it the same way `start_unix_servers`/`start_udp_servers` do below.
         Transport::Tcp => match start_tcp_servers(config.clone()).await {
             Ok(handles) => {
                 join_handles.extend(handles);
@@ -113,7 +192,32 @@ async fn start_servers(config: ServerConfig) -> std::io::Result<Vec<JoinHandle<(
                 return Err(e);
             }
         },
-        Transport::Udp => todo!(),
+        Transport::Unix => match start_unix_servers(
+            config.bind_location.unix_path()?,
+            config.clone(),
+            shutdown,
+        ) {
+            Ok(handles) => {
+                join_handles.extend(handles);
+            }
+            Err(e) => {
+                for join_handle in join_handles {
+                    join_handle.abort();
+                }
+                return Err(e);
+            }
+        },
+        Transport::Udp => match start_udp_servers(config.clone(), shutdown).await {
+            Ok(handles) => {
+                join_handles.extend(handles);
+            }
+            Err(e) => {
+                for join_handle in join_handles {
+                    join_handle.abort();
+                }
+                return Err(e);
+            }
+        },
     }
 
     if join_handles.is_empty() {
@@ -222,6 +326,8 @@ fn main() {
     runtime.block_on(async move {
         let (_watcher, mut config_rx) = start_notify_thread(config_paths.clone());
 
+        let mut running_servers: HashMap<String, RunningServer> = HashMap::new();
+
         loop {
             let configs = match config::load_configs(&config_paths).await {
                 Ok(c) => c,
@@ -260,10 +366,6 @@ fn main() {
                 return;
             }
 
-            println!("\nStarting {} server(s)..", configs.len());
-
-            let mut join_handles = vec![];
-
             let server_configs = match config::create_server_configs(configs).await {
                 Ok(c) => c,
                 Err(e) => {
@@ -272,19 +374,121 @@ fn main() {
                     return;
                 }
             };
+
+            let mut next_servers: HashMap<String, RunningServer> =
+                HashMap::with_capacity(server_configs.len());
+
+            // Servers whose definition changed: the old listener at each one's address must be
+            // fully torn down before its replacement binds the same address, or the bind races
+            // the still-live old listener and fails with `AddrInUse`. That only constrains each
+            // server's *own* replacement though, so rather than draining them one at a time here
+            // (which would serialize their up-to-`SHUTDOWN_GRACE_PERIOD` drains back to back and
+            // block every later server in `server_configs`, changed or not, from starting until
+            // all of them finished), flip every changed server's shutdown tripwire up front and
+            // drain each on its own task so the grace periods run concurrently. The replacements
+            // are then started in order, each once its own retirement and those of the changed
+            // servers before it have finished, so together they wait about one grace period.
+            let mut changed_servers = Vec::new();
+
             for server_config in server_configs {
-                join_handles.extend(start_servers(server_config).await.unwrap());
+                let key = server_config.bind_location.to_string();
+                let fingerprint = config_fingerprint(&server_config);
+
+                if let Some(running_server) = running_servers.remove(&key) {
+                    if running_server.fingerprint == fingerprint {
+                        // Unchanged since the last reload: leave it running untouched.
+                        next_servers.insert(key, running_server);
+                        continue;
+                    }
+                    println!("Config changed for {key}, restarting..");
+                    running_server.shutdown_controller.begin_shutdown();
+                    let retire_handle = tokio::spawn(retire_server(running_server));
+                    changed_servers.push((key, server_config, fingerprint, retire_handle));
+                    continue;
+                }
+
+                println!("Starting new server at {key}..");
+                let shutdown_controller = ShutdownController::new();
+                let join_handles = start_servers(server_config, shutdown_controller.token())
+                    .await
+                    .unwrap();
+                next_servers.insert(
+                    key,
+                    RunningServer {
+                        join_handles,
+                        shutdown_controller,
+                        fingerprint,
+                    },
+                );
             }
 
-            config_rx.recv().await.unwrap();
+            for (key, server_config, fingerprint, retire_handle) in changed_servers {
+                retire_handle.await.unwrap();
+
+                let shutdown_controller = ShutdownController::new();
+                let join_handles = start_servers(server_config, shutdown_controller.token())
+                    .await
+                    .unwrap();
+                next_servers.insert(
+                    key,
+                    RunningServer {
+                        join_handles,
+                        shutdown_controller,
+                        fingerprint,
+                    },
+                );
+            }
 
-            println!("Configs changed, restarting servers in 3 seconds..");
+            // Anything still left in `running_servers` has no matching entry in the new config at
+            // all, so it's being removed rather than replaced; none of these addresses are about
+            // to be rebound, so draining them concurrently is safe.
+            let removed_servers: Vec<RunningServer> = running_servers.into_values().collect();
+            if !removed_servers.is_empty() {
+                println!("Draining {} removed server(s)..", removed_servers.len());
+
+                // Flip every tripwire up front, then spawn each drain onto its own task and join
+                // them all, the same way the changed-servers loop above does; awaiting
+                // retire_server in this for loop directly would run the drains one at a time and
+                // take up to `removed_servers.len() * SHUTDOWN_GRACE_PERIOD` instead of ~one
+                // grace period total.
+                let retire_handles: Vec<JoinHandle<()>> = removed_servers
+                    .into_iter()
+                    .map(|removed_server| {
+                        removed_server.shutdown_controller.begin_shutdown();
+                        tokio::spawn(retire_server(removed_server))
+                    })
+                    .collect();
+                for retire_handle in retire_handles {
+                    retire_handle.await.unwrap();
+                }
+            }
 
-            for join_handle in join_handles {
-                join_handle.abort();
+            running_servers = next_servers;
+
+            tokio::select! {
+                _ = wait_for_shutdown_signal() => {
+                    println!(
+                        "Received shutdown signal, draining {} server(s)..",
+                        running_servers.len()
+                    );
+                    let retire_handles: Vec<JoinHandle<()>> = running_servers
+                        .into_values()
+                        .map(|running_server| {
+                            running_server.shutdown_controller.begin_shutdown();
+                            tokio::spawn(retire_server(running_server))
+                        })
+                        .collect();
+                    for retire_handle in retire_handles {
+                        retire_handle.await.unwrap();
+                    }
+                    return;
+                }
+                received = config_rx.recv() => {
+                    received.unwrap();
+                }
             }
 
-            tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+            println!("Configs changed, checking which server(s) differ..");
 
             // Remove any extra events
             while config_rx.try_recv().is_ok() {}
@@ -1,25 +1,224 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use async_trait::async_trait;
+use log::{debug, warn};
+use tokio::task::JoinHandle;
 
 use crate::address::NetLocation;
 use crate::async_stream::AsyncStream;
+use crate::noop_stream::NoopStream;
 use crate::option_util::NoneOrOne;
+use crate::resolver::Resolver;
+use crate::shutdown::ShutdownToken;
+use crate::tcp::tcp_client_connector::TcpClientConnector;
 use crate::tcp::tcp_handler::{TcpServerHandler, TcpServerSetupResult};
 
+/// How often a healthy-looking target is re-probed, and an unhealthy one is retried.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a single probe connection is given to complete before the target is marked
+/// unhealthy for this round.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// One backend a `PortForwardServerHandler` can forward to, and its weight in the weighted
+/// failover selection among whichever targets are currently healthy.
+#[derive(Debug, Clone)]
+pub(crate) struct PortForwardTarget {
+    pub(crate) location: NetLocation,
+    pub(crate) weight: u32,
+}
+
+impl PortForwardTarget {
+    pub(crate) fn new(location: NetLocation, weight: u32) -> Self {
+        Self { location, weight }
+    }
+}
+
+#[derive(Debug)]
+struct HealthTrackedTarget {
+    target: PortForwardTarget,
+    // starts healthy so the first real check, rather than a guess, is what can mark it down.
+    healthy: AtomicBool,
+}
+
 #[derive(Debug)]
 pub(crate) struct PortForwardServerHandler {
-    targets: Vec<NetLocation>,
-    next_target_index: AtomicU32,
+    targets: Vec<HealthTrackedTarget>,
+    // Smooth-weighted-round-robin state (one entry per `targets` index, same scheme nginx uses):
+    // every selection adds each candidate's weight to its running total, picks the largest, then
+    // subtracts the candidates' combined weight from just the winner. That spreads picks out
+    // evenly over time instead of the bursty runs a simple `counter % total_weight` produces,
+    // and survives the healthy set changing between calls since indices are stable.
+    current_weights: Mutex<Vec<i64>>,
+    client_connector: TcpClientConnector,
+    resolver: Arc<dyn Resolver>,
 }
 
 impl PortForwardServerHandler {
-    pub(crate) fn new(targets: Vec<NetLocation>) -> Self {
+    /// `client_connector`/`resolver` are what `spawn_health_checks` below dials probes with; the
+    /// caller that builds this handler from config is expected to wrap it in an `Arc` and call
+    /// `spawn_health_checks` once, folding the returned `JoinHandle` in with its other server
+    /// tasks so it's torn down the same way on reload/shutdown.
+    pub(crate) fn new(
+        targets: Vec<PortForwardTarget>,
+        client_connector: TcpClientConnector,
+        resolver: Arc<dyn Resolver>,
+    ) -> Self {
+        let current_weights = Mutex::new(vec![0i64; targets.len()]);
         Self {
-            targets,
-            next_target_index: AtomicU32::new(0),
+            targets: targets
+                .into_iter()
+                .map(|target| HealthTrackedTarget {
+                    target,
+                    healthy: AtomicBool::new(true),
+                })
+                .collect(),
+            current_weights,
+            client_connector,
+            resolver,
+        }
+    }
+
+    /// Passive counterpart to the active `spawn_health_checks` probe: the caller that actually
+    /// dials `location` (once `TcpServerSetupResult::TcpForward` reaches the copy loop) calls
+    /// this on a connect failure to eject the target immediately, rather than waiting up to
+    /// `HEALTH_CHECK_INTERVAL` for the next active probe to notice.
+    pub(crate) fn report_failure(&self, location: &NetLocation) {
+        if let Some(tracked_target) = self
+            .targets
+            .iter()
+            .find(|tracked_target| &tracked_target.target.location == location)
+        {
+            if tracked_target.healthy.swap(false, Ordering::Relaxed) {
+                warn!(
+                    "Port forward target {location} failed to connect, routing around it until its next successful health check"
+                );
+            }
         }
     }
+
+    /// Spawns the background loop that actively probes every target on `HEALTH_CHECK_INTERVAL`,
+    /// the same way `start_udp_servers` spawns its idle sweep alongside the accept loop. The
+    /// returned `JoinHandle` is meant to be folded into the rest of a generation's server tasks so
+    /// it gets torn down on reload/shutdown the same as everything else.
+    pub(crate) fn spawn_health_checks(
+        self: &Arc<Self>,
+        mut shutdown: ShutdownToken,
+    ) -> JoinHandle<()> {
+        let handler = self.clone();
+        tokio::spawn(async move {
+            if handler.targets.len() <= 1 {
+                // nothing to fail over to, so there's no point spending connections probing it.
+                return;
+            }
+
+            let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.wait_for_shutdown() => break,
+                }
+
+                for tracked_target in &handler.targets {
+                    let was_healthy = tracked_target.healthy.load(Ordering::Relaxed);
+                    let mut probe_stream: Box<dyn AsyncStream> = Box::new(NoopStream);
+                    let is_healthy = tokio::time::timeout(
+                        HEALTH_CHECK_TIMEOUT,
+                        handler.client_connector.connect(
+                            &mut probe_stream,
+                            tracked_target.target.location.clone(),
+                            &handler.resolver,
+                        ),
+                    )
+                    .await
+                    .map(|result| result.is_ok())
+                    .unwrap_or(false);
+
+                    tracked_target.healthy.store(is_healthy, Ordering::Relaxed);
+
+                    if was_healthy && !is_healthy {
+                        warn!(
+                            "Port forward target {} failed its health check, routing around it",
+                            tracked_target.target.location
+                        );
+                    } else if !was_healthy && is_healthy {
+                        debug!(
+                            "Port forward target {} is healthy again",
+                            tracked_target.target.location
+                        );
+                    }
+                }
+            }
+        })
+    }
+
+    /// Smooth weighted round robin over whichever targets are currently healthy, falling back to
+    /// every target (including unhealthy ones) if none are, so a stuck or overly strict health
+    /// check never takes the whole forward down.
+    fn select_target(&self) -> &PortForwardTarget {
+        let weights: Vec<u32> = self
+            .targets
+            .iter()
+            .map(|tracked_target| tracked_target.target.weight)
+            .collect();
+        let healthy: Vec<bool> = self
+            .targets
+            .iter()
+            .map(|tracked_target| tracked_target.healthy.load(Ordering::Relaxed))
+            .collect();
+
+        let mut current_weights = self.current_weights.lock().unwrap();
+        let selected_index = smooth_weighted_round_robin_select(&weights, &healthy, &mut current_weights);
+
+        &self.targets[selected_index].target
+    }
+}
+
+/// Core smooth-weighted-round-robin selection, split out from `select_target` so it can be
+/// exercised without a real `PortForwardServerHandler` (which needs a live `TcpClientConnector`/
+/// `Resolver` to construct): picks among indices where `healthy[index]` is true, falling back to
+/// every index if none are healthy, and updates `current_weights` (one entry per `weights`/
+/// `healthy` index) in place using the same scheme nginx uses - each candidate's weight is added
+/// to its running total, the largest total wins, then the combined candidate weight is subtracted
+/// from just the winner.
+fn smooth_weighted_round_robin_select(
+    weights: &[u32],
+    healthy: &[bool],
+    current_weights: &mut [i64],
+) -> usize {
+    assert_eq!(weights.len(), healthy.len());
+    assert_eq!(weights.len(), current_weights.len());
+
+    if weights.len() == 1 {
+        return 0;
+    }
+
+    let healthy_indices: Vec<usize> = (0..weights.len()).filter(|&index| healthy[index]).collect();
+    let candidate_indices = if healthy_indices.is_empty() {
+        (0..weights.len()).collect()
+    } else {
+        healthy_indices
+    };
+
+    let total_weight: i64 = candidate_indices
+        .iter()
+        .map(|&index| weights[index].max(1) as i64)
+        .sum();
+
+    let mut selected_index = candidate_indices[0];
+    let mut selected_weight = i64::MIN;
+    for &index in &candidate_indices {
+        let effective_weight = weights[index].max(1) as i64;
+        current_weights[index] += effective_weight;
+        if current_weights[index] > selected_weight {
+            selected_weight = current_weights[index];
+            selected_index = index;
+        }
+    }
+    current_weights[selected_index] -= total_weight;
+
+    selected_index
 }
 
 #[async_trait]
@@ -28,15 +227,10 @@ impl TcpServerHandler for PortForwardServerHandler {
         &self,
         server_stream: Box<dyn AsyncStream>,
     ) -> std::io::Result<TcpServerSetupResult> {
-        let location = if self.targets.len() == 1 {
-            &self.targets[0]
-        } else {
-            let target_index = self.next_target_index.fetch_add(1, Ordering::Relaxed) as usize;
-            &self.targets[target_index % self.targets.len()]
-        };
+        let location = self.select_target().location.clone();
 
         Ok(TcpServerSetupResult::TcpForward {
-            remote_location: location.clone(),
+            remote_location: location,
             stream: server_stream,
             need_initial_flush: false,
             connection_success_response: None,
@@ -45,3 +239,74 @@ impl TcpServerHandler for PortForwardServerHandler {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_target_is_always_selected() {
+        let mut current_weights = vec![0i64];
+        for _ in 0..3 {
+            assert_eq!(
+                smooth_weighted_round_robin_select(&[5], &[true], &mut current_weights),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn equal_weights_alternate_evenly() {
+        let weights = [1, 1];
+        let healthy = [true, true];
+        let mut current_weights = vec![0i64, 0i64];
+
+        let picks: Vec<usize> = (0..4)
+            .map(|_| smooth_weighted_round_robin_select(&weights, &healthy, &mut current_weights))
+            .collect();
+        assert_eq!(picks, vec![0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn heavier_weight_is_picked_proportionally_more_often() {
+        // nginx's canonical smooth-weighted-round-robin example: weights 5, 1, 1 over 7 rounds
+        // should select index 0 five times, interleaved rather than bursted.
+        let weights = [5, 1, 1];
+        let healthy = [true, true, true];
+        let mut current_weights = vec![0i64; 3];
+
+        let picks: Vec<usize> = (0..7)
+            .map(|_| smooth_weighted_round_robin_select(&weights, &healthy, &mut current_weights))
+            .collect();
+        assert_eq!(picks, vec![0, 0, 1, 0, 2, 0, 0]);
+        assert_eq!(picks.iter().filter(|&&index| index == 0).count(), 5);
+    }
+
+    #[test]
+    fn unhealthy_targets_are_skipped_while_any_healthy_target_remains() {
+        let weights = [1, 1, 1];
+        let healthy = [true, false, true];
+        let mut current_weights = vec![0i64; 3];
+
+        for _ in 0..6 {
+            let selected =
+                smooth_weighted_round_robin_select(&weights, &healthy, &mut current_weights);
+            assert_ne!(selected, 1, "unhealthy target should never be selected");
+        }
+    }
+
+    #[test]
+    fn falls_back_to_every_target_when_none_are_healthy() {
+        let weights = [1, 2];
+        let healthy = [false, false];
+        let mut current_weights = vec![0i64; 2];
+
+        let picks: Vec<usize> = (0..3)
+            .map(|_| smooth_weighted_round_robin_select(&weights, &healthy, &mut current_weights))
+            .collect();
+        // still alternates/weights normally across the full (unhealthy) set rather than panicking
+        // or always picking the same index.
+        assert!(picks.contains(&0));
+        assert!(picks.contains(&1));
+    }
+}
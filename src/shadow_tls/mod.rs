@@ -4,7 +4,8 @@ mod shadow_tls_server_handler;
 mod shadow_tls_stream;
 
 pub use shadow_tls_server_handler::{
-    ParsedClientHello, ShadowTlsServerTarget, ShadowTlsServerTargetHandshake,
+    ClientHelloFingerprintProfile, ParsedClientHello, ShadowTlsFallback,
+    ShadowTlsHandshakeVersion, ShadowTlsServerTarget, ShadowTlsServerTargetHandshake,
     feed_server_connection, read_client_hello, setup_shadowtls_server_stream,
 };
 
@@ -6,9 +6,10 @@
 /// - https://tls13.xargs.org/#client-hello/annotated
 use std::fmt::Debug;
 use std::io::Cursor;
-use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
+use log::debug;
 use tokio::io::AsyncWriteExt;
 
 use super::shadow_tls_hmac::ShadowTlsHmac;
@@ -34,21 +35,40 @@ impl Debug for ShadowTlsXorContext {
     }
 }
 
+/// Which TLS version the upstream handshake server at `ShadowTlsServerTargetHandshake::Remote`
+/// is expected to negotiate. TLS 1.3 cover traffic hides the entire post-ServerHello handshake
+/// flight inside opaque application-data records; TLS 1.2 cover traffic sends that flight as
+/// plaintext/handshake-typed records instead, and only a TLS 1.3 `ServerHello` is required to
+/// carry a `supported_versions` extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadowTlsHandshakeVersion {
+    #[default]
+    Tls13,
+    Tls12,
+}
+
 #[derive(Debug)]
 pub struct ShadowTlsServerTarget {
     initial_hmac: ShadowTlsHmac,
     initial_xor_context: ShadowTlsXorContext,
     handshake: ShadowTlsServerTargetHandshake,
+    handshake_version: ShadowTlsHandshakeVersion,
     handler: Box<dyn TcpServerHandler>,
     override_proxy_provider: NoneOrOne<Arc<ClientProxySelector<TcpClientConnector>>>,
+    fallback: Option<ShadowTlsFallback>,
+    fingerprint_profile: Option<ClientHelloFingerprintProfile>,
 }
 
 impl ShadowTlsServerTarget {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         password: String,
         handshake: ShadowTlsServerTargetHandshake,
+        handshake_version: ShadowTlsHandshakeVersion,
         handler: Box<dyn TcpServerHandler>,
         override_proxy_provider: NoneOrOne<Arc<ClientProxySelector<TcpClientConnector>>>,
+        fallback: Option<ShadowTlsFallback>,
+        fingerprint_profile: Option<ClientHelloFingerprintProfile>,
     ) -> Self {
         let password_bytes = password.into_bytes();
         let hmac_key = aws_lc_rs::hmac::Key::new(
@@ -62,12 +82,162 @@ impl ShadowTlsServerTarget {
             initial_hmac,
             initial_xor_context: ShadowTlsXorContext(initial_xor_context),
             handshake,
+            handshake_version,
             handler,
             override_proxy_provider,
+            fallback,
+            fingerprint_profile,
         }
     }
 }
 
+/// Where to splice a connection that fails ShadowTLS auth (missing session id, HMAC mismatch,
+/// or a TLS version the server doesn't expect) so that an active prober sees a legitimate TLS
+/// handshake instead of an abrupt reset. Reachable even when the primary handshake target is
+/// `Local`, since the fallback is a distinct, independently-dialed target.
+#[derive(Debug)]
+pub struct ShadowTlsFallback {
+    location: NetLocation,
+    override_proxy_provider: NoneOrOne<Arc<ClientProxySelector<TcpClientConnector>>>,
+}
+
+impl ShadowTlsFallback {
+    pub fn new(
+        location: NetLocation,
+        override_proxy_provider: NoneOrOne<Arc<ClientProxySelector<TcpClientConnector>>>,
+    ) -> Self {
+        Self {
+            location,
+            override_proxy_provider,
+        }
+    }
+}
+
+/// The 16 reserved GREASE codepoints from RFC 8701 that real browsers sprinkle into cipher
+/// suites and extensions, in a random position and quantity each connection, so that
+/// middleboxes and servers don't ossify on a fixed set of values. A fingerprint match ignores
+/// them wherever they appear rather than requiring a specific count.
+///
+/// These are *not* every `0x?A?A` value: GREASE also requires the two bytes to be identical
+/// (`0x1A2A`/`0x3A5A` are not GREASE), so membership in
+/// this explicit list is checked rather than a bitmask.
+const GREASE_VALUES: [u16; 16] = [
+    0x0A0A, 0x1A1A, 0x2A2A, 0x3A3A, 0x4A4A, 0x5A5A, 0x6A6A, 0x7A7A, 0x8A8A, 0x9A9A, 0xAAAA, 0xBABA,
+    0xCACA, 0xDADA, 0xEAEA, 0xFAFA,
+];
+
+fn is_grease_value(value: u16) -> bool {
+    GREASE_VALUES.contains(&value)
+}
+
+/// Expected cipher-suite/extension shape of a real browser's ClientHello, used to reject
+/// connections whose hello looks machine-generated (e.g. a bare `rustls` or custom TLS client)
+/// rather than like the browser the deployment is trying to camouflage as. Checked before
+/// `setup_shadowtls_server_stream` commits to the proxied handler; a non-matching hello is
+/// diverted to `ShadowTlsFallback` the same way an HMAC or session-id failure is.
+#[derive(Debug, Clone)]
+pub struct ClientHelloFingerprintProfile {
+    /// Extension types that must be present somewhere in the hello, e.g. ALPN (0x0010),
+    /// key_share (0x0033), signature_algorithms (0x000d).
+    required_extensions: Vec<u16>,
+    /// Cipher suites that must be present somewhere in the hello's cipher suite list.
+    required_cipher_suites: Vec<u16>,
+    /// Expected relative extension order, with GREASE values excluded since their position is
+    /// random. Checked as a subsequence of the observed (GREASE-filtered) order rather than an
+    /// exact match, since real browsers already vary the rest of the extension list (e.g. by
+    /// feature flags, OS, or enabled experiments) well beyond what GREASE alone explains; an
+    /// exact match would reject most real hellos unless this profile were kept byte-perfectly
+    /// in sync with the browser build being camouflaged.
+    extension_order: Vec<u16>,
+}
+
+impl ClientHelloFingerprintProfile {
+    pub fn new(
+        required_extensions: Vec<u16>,
+        required_cipher_suites: Vec<u16>,
+        extension_order: Vec<u16>,
+    ) -> Self {
+        Self {
+            required_extensions,
+            required_cipher_suites,
+            extension_order,
+        }
+    }
+
+    fn matches(&self, extension_types: &[u16], cipher_suites: &[u16]) -> bool {
+        if !self
+            .required_extensions
+            .iter()
+            .all(|required| extension_types.contains(required))
+        {
+            return false;
+        }
+
+        if !self
+            .required_cipher_suites
+            .iter()
+            .all(|required| cipher_suites.contains(required))
+        {
+            return false;
+        }
+
+        let observed_order: Vec<u16> = extension_types
+            .iter()
+            .copied()
+            .filter(|extension_type| !is_grease_value(*extension_type))
+            .collect();
+
+        is_subsequence(&self.extension_order, &observed_order)
+    }
+}
+
+/// Whether every element of `needle` appears in `haystack`, in the same relative order (not
+/// necessarily contiguously).
+fn is_subsequence(needle: &[u16], haystack: &[u16]) -> bool {
+    let mut haystack = haystack.iter();
+    needle
+        .iter()
+        .all(|wanted| haystack.any(|observed| observed == wanted))
+}
+
+/// Validates a follow-up TLS record's header while reassembling a ClientHello fragmented across
+/// multiple records: it must be another handshake record at the same legacy TLS version as the
+/// first one, or the client isn't actually continuing that ClientHello.
+fn validate_fragment_record_header(
+    header_bytes: &[u8],
+    expected_legacy_version_major: u8,
+    expected_legacy_version_minor: u8,
+) -> std::io::Result<()> {
+    if header_bytes[0] != CONTENT_TYPE_HANDSHAKE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "expected a handshake record while reassembling a fragmented ClientHello",
+        ));
+    }
+    if header_bytes[1] != expected_legacy_version_major
+        || header_bytes[2] != expected_legacy_version_minor
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "inconsistent TLS record version while reassembling a fragmented ClientHello",
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects a fragmented ClientHello once the reassembled body would exceed
+/// `MAX_REASSEMBLED_CLIENT_HELLO_LEN`, so a malicious or broken peer can't force unbounded
+/// buffering by declaring (or drip-feeding) an ever-growing handshake body.
+fn check_reassembly_size_limit(reassembled_len: usize) -> std::io::Result<()> {
+    if reassembled_len > MAX_REASSEMBLED_CLIENT_HELLO_LEN + 4 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "fragmented client hello exceeded the reassembly size limit",
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum ShadowTlsServerTargetHandshake {
     Local(Arc<rustls::ServerConfig>),
@@ -105,6 +275,11 @@ const CONTENT_TYPE_APPLICATION_DATA: u8 = 0x17;
 const HANDSHAKE_TYPE_SERVER_HELLO: u8 = 0x02;
 const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 0x01;
 
+// the handshake message length field is a u24, but a ClientHello legitimately fragmented across
+// records (hybrid/PQ key shares, or a middlebox splitting frames) is never going to approach
+// that; cap reassembly well below it so a malicious/broken peer can't make us buffer forever.
+const MAX_REASSEMBLED_CLIENT_HELLO_LEN: usize = 65535;
+
 // retry request random value, see https://datatracker.ietf.org/doc/html/rfc8446#section-4.1.3
 // TODO: should we also check to disallow TLS1.2/TLS1.1 client downgrade requests?
 const RETRY_REQUEST_RANDOM_BYTES: [u8; 32] = [
@@ -121,6 +296,7 @@ pub async fn setup_shadowtls_server_stream(
 ) -> std::io::Result<TcpServerSetupResult> {
     let ParsedClientHello {
         client_hello_frame,
+        client_hello_message,
         client_hello_record_legacy_version_major,
         client_hello_record_legacy_version_minor,
         client_hello_content_version_major,
@@ -128,62 +304,93 @@ pub async fn setup_shadowtls_server_stream(
         parsed_digest,
         client_reader,
         supports_tls13: client_supports_tls13,
+        extension_types,
+        cipher_suites,
         ..
     } = parsed_client_hello;
 
-    let ParsedClientHelloDigest {
-        client_hello_digest,
-        client_hello_digest_start_index,
-        client_hello_digest_end_index,
-    } = match parsed_digest {
+    let parsed_digest = match parsed_digest {
         Some(d) => d,
         None => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
+            return fallback_or_err(
+                target,
+                server_stream,
+                client_reader,
+                client_hello_frame,
                 "client did not send a 32-byte session id",
-            ));
+            );
         }
     };
+    let ParsedClientHelloDigest {
+        client_hello_digest,
+        client_hello_digest_start_index,
+        client_hello_digest_end_index,
+    } = parsed_digest;
 
-    if !client_supports_tls13 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
+    if target.handshake_version == ShadowTlsHandshakeVersion::Tls13 && !client_supports_tls13 {
+        return fallback_or_err(
+            target,
+            server_stream,
+            client_reader,
+            client_hello_frame,
             "client does not support TLS1.3",
-        ));
+        );
     }
 
     if client_hello_record_legacy_version_major != 3
         || client_hello_record_legacy_version_minor != 1
     {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
+        return fallback_or_err(
+            target,
+            server_stream,
+            client_reader,
+            client_hello_frame,
             format!(
                 "expected client TLS record protocol 1.0 (major/minor 3.1), got major/minor {client_hello_record_legacy_version_major}.{client_hello_record_legacy_version_minor}"
             ),
-        ));
+        );
     }
 
     if client_hello_content_version_major != 3 || client_hello_content_version_minor != 3 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
+        return fallback_or_err(
+            target,
+            server_stream,
+            client_reader,
+            client_hello_frame,
             format!(
                 "expected client TLS content protocol 1.2 (major/minor 3.3), got major/minor {client_hello_content_version_major}.{client_hello_content_version_minor}"
             ),
-        ));
+        );
     }
 
-    // verify the hmac digest
+    if let Some(ref fingerprint_profile) = target.fingerprint_profile {
+        if !fingerprint_profile.matches(&extension_types, &cipher_suites) {
+            return fallback_or_err(
+                target,
+                server_stream,
+                client_reader,
+                client_hello_frame,
+                "clienthello fingerprint did not match the configured camouflage profile",
+            );
+        }
+    }
+
+    // verify the hmac digest. this is computed over the reassembled logical handshake message
+    // rather than `client_hello_frame`, so it produces the same result whether or not the
+    // ClientHello was fragmented across multiple TLS records.
     let mut hmac_client_hello = target.initial_hmac.clone();
-    hmac_client_hello.update(&client_hello_frame[TLS_HEADER_LEN..client_hello_digest_start_index]);
+    hmac_client_hello.update(&client_hello_message[..client_hello_digest_start_index]);
     hmac_client_hello.update(&[0; 4]);
-    hmac_client_hello.update(&client_hello_frame[client_hello_digest_end_index..]);
+    hmac_client_hello.update(&client_hello_message[client_hello_digest_end_index..]);
 
     if client_hello_digest != hmac_client_hello.finalized_digest() {
-        // TODO: forward to handshake server
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
+        return fallback_or_err(
+            target,
+            server_stream,
+            client_reader,
+            client_hello_frame,
             "hmac tag mismatch",
-        ));
+        );
     }
 
     let shadow_tls_stream = match target.handshake {
@@ -200,6 +407,7 @@ pub async fn setup_shadowtls_server_stream(
                 client_hello_frame,
                 &target.initial_hmac,
                 &target.initial_xor_context,
+                target.handshake_version,
                 location.clone(),
                 client_connector,
                 resolver,
@@ -241,8 +449,51 @@ pub async fn setup_shadowtls_server_stream(
     target_setup_result
 }
 
+/// Handles a ShadowTLS auth failure: if `target` has a fallback configured, transparently splice
+/// the connection into a relay to the real handshake server (resending the already-consumed
+/// ClientHello frame verbatim) instead of erroring. This runs before any byte is written back to
+/// the client, so the timing of the response doesn't leak the auth decision to an active prober.
+fn fallback_or_err(
+    target: &ShadowTlsServerTarget,
+    server_stream: Box<dyn AsyncStream>,
+    client_reader: StreamReader,
+    client_hello_frame: Vec<u8>,
+    reason: impl std::fmt::Display,
+) -> std::io::Result<TcpServerSetupResult> {
+    let Some(fallback) = target.fallback.as_ref() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            reason.to_string(),
+        ));
+    };
+
+    debug!(
+        "shadow-tls auth failed ({reason}), relaying to fallback handshake server at {}",
+        fallback.location
+    );
+
+    let mut initial_remote_data = client_hello_frame;
+    initial_remote_data.extend_from_slice(client_reader.unparsed_data());
+
+    Ok(TcpServerSetupResult::TcpForward {
+        remote_location: fallback.location.clone(),
+        stream: server_stream,
+        need_initial_flush: false,
+        connection_success_response: None,
+        initial_remote_data: Some(initial_remote_data.into_boxed_slice()),
+        override_proxy_provider: fallback.override_proxy_provider.clone(),
+    })
+}
+
 pub struct ParsedClientHello {
+    // the raw TLS records exactly as sent by the client, concatenated in order; may span
+    // multiple TLS records when the ClientHello handshake message was fragmented, and must be
+    // forwarded byte-for-byte so the upstream handshake transcript stays valid.
     pub client_hello_frame: Vec<u8>,
+    // the reassembled handshake message (type + u24 length + body) with all per-record TLS
+    // framing stripped out; extension/session-id parsing and the HMAC digest both operate on
+    // this logical message rather than on `client_hello_frame`.
+    pub client_hello_message: Vec<u8>,
     pub client_hello_record_legacy_version_major: u8,
     pub client_hello_record_legacy_version_minor: u8,
     pub client_hello_content_version_major: u8,
@@ -251,10 +502,17 @@ pub struct ParsedClientHello {
     pub client_reader: StreamReader,
     pub requested_server_name: Option<String>,
     pub supports_tls13: bool,
+    // cipher suites and extension types, in the order the client sent them, used by
+    // `ClientHelloFingerprintProfile` to check whether the hello resembles a real browser's.
+    pub cipher_suites: Vec<u16>,
+    pub extension_types: Vec<u16>,
 }
 
 pub struct ParsedClientHelloDigest {
     pub client_hello_digest: Vec<u8>,
+    // both indices are relative to `ParsedClientHello::client_hello_message`, not
+    // `client_hello_frame`, so the HMAC is computed over the reassembled logical handshake
+    // message regardless of how many TLS records the ClientHello was split across.
     pub client_hello_digest_start_index: usize,
     pub client_hello_digest_end_index: usize,
 }
@@ -287,25 +545,77 @@ pub async fn read_client_hello(
         u16::from_be_bytes([client_tls_header_bytes[3], client_tls_header_bytes[4]]) as usize;
     let client_payload_bytes = client_reader
         .read_slice(server_stream, client_payload_len)
-        .await?;
+        .await?
+        .to_vec();
 
-    let mut client_hello = BufReader::new(client_payload_bytes);
-    if client_hello.read_u8()? != HANDSHAKE_TYPE_CLIENT_HELLO {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "expected ClientHello",
-        ));
-    }
+    // raw records exactly as received, used to relay the ClientHello byte-for-byte
+    let mut client_hello_frame =
+        Vec::with_capacity(client_tls_header_bytes.len() + client_payload_len);
+    client_hello_frame.extend_from_slice(&client_tls_header_bytes);
+    client_hello_frame.extend_from_slice(&client_payload_bytes);
 
-    let client_hello_message_len = client_hello.read_u24_be()? as usize;
-    // this should be 4 bytes less than the payload length (handshake type + 3 bytes length)
-    if client_hello_message_len + 4 != client_payload_len {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "client hello message length mismatch",
-        ));
+    // reassembled handshake message (type + u24 length + body) with per-record framing
+    // stripped out; starts out as just the first record's payload
+    let mut client_hello_message = client_payload_bytes;
+
+    {
+        let mut header_reader = BufReader::new(&client_hello_message);
+        if header_reader.read_u8()? != HANDSHAKE_TYPE_CLIENT_HELLO {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "expected ClientHello",
+            ));
+        }
+        let client_hello_message_len = header_reader.read_u24_be()? as usize;
+        if client_hello_message_len > MAX_REASSEMBLED_CLIENT_HELLO_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("client hello message too large to reassemble: {client_hello_message_len}"),
+            ));
+        }
+
+        // 4 bytes for the handshake type + u24 length that precede the body
+        let full_message_len = client_hello_message_len + 4;
+
+        // A ClientHello whose handshake body exceeds one record is legal (RFC 8446) and
+        // increasingly common with hybrid key-share extensions; keep reading follow-up TLS
+        // records and appending their payloads until we have the full declared length.
+        while client_hello_message.len() < full_message_len {
+            let next_header_bytes = client_reader
+                .read_slice(server_stream, TLS_HEADER_LEN)
+                .await?
+                .to_vec();
+
+            validate_fragment_record_header(
+                &next_header_bytes,
+                client_legacy_version_major,
+                client_legacy_version_minor,
+            )?;
+
+            let next_payload_len =
+                u16::from_be_bytes([next_header_bytes[3], next_header_bytes[4]]) as usize;
+            let next_payload_bytes = client_reader
+                .read_slice(server_stream, next_payload_len)
+                .await?
+                .to_vec();
+
+            check_reassembly_size_limit(client_hello_message.len() + next_payload_bytes.len())?;
+
+            client_hello_frame.extend_from_slice(&next_header_bytes);
+            client_hello_frame.extend_from_slice(&next_payload_bytes);
+            client_hello_message.extend_from_slice(&next_payload_bytes);
+        }
+
+        if client_hello_message.len() != full_message_len {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "client hello message length mismatch after reassembly",
+            ));
+        }
     }
 
+    let mut client_hello = BufReader::new(&client_hello_message[4..]);
+
     let client_version_major = client_hello.read_u8()?;
     let client_version_minor = client_hello.read_u8()?;
     let record_protocol_version_ok = client_version_major == 0x03
@@ -327,12 +637,16 @@ pub async fn read_client_hello(
     let parsed_digest = if client_session_id_len == 32 {
         let client_session_id = client_hello.read_slice(32)?;
 
-        // save the hmac digest and session id position for validation once we know the server name
+        // save the hmac digest and session id position for validation once we know the server name.
+        // `client_hello` is positioned relative to `client_hello_message[4..]` (the handshake body,
+        // with the type+u24-length header stripped), so +4 maps back to a `client_hello_message`-
+        // relative offset; the HMAC is computed over that reassembled logical message rather than
+        // the raw (possibly multi-record) `client_hello_frame`.
         let client_hello_digest = client_session_id[28..].to_vec();
-        let post_session_id_index = client_hello.position();
+        let post_session_id_index = client_hello.position() + 4;
 
-        let client_hello_digest_start_index = TLS_HEADER_LEN + post_session_id_index - 4;
-        let client_hello_digest_end_index = TLS_HEADER_LEN + post_session_id_index;
+        let client_hello_digest_start_index = post_session_id_index - 4;
+        let client_hello_digest_end_index = post_session_id_index;
 
         Some(ParsedClientHelloDigest {
             client_hello_digest,
@@ -347,7 +661,11 @@ pub async fn read_client_hello(
     };
 
     let client_cipher_suite_len = client_hello.read_u16_be()?;
-    client_hello.skip(client_cipher_suite_len as usize)?;
+    let client_cipher_suite_bytes = client_hello.read_slice(client_cipher_suite_len as usize)?;
+    let cipher_suites: Vec<u16> = client_cipher_suite_bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
 
     let client_compression_method_len = client_hello.read_u8()?;
     client_hello.skip(client_compression_method_len as usize)?;
@@ -359,10 +677,12 @@ pub async fn read_client_hello(
 
     let mut requested_server_name: Option<String> = None;
     let mut client_supports_tls13 = false;
+    let mut extension_types: Vec<u16> = Vec::new();
 
     while !client_extensions.is_consumed() {
         let extension_type = client_extensions.read_u16_be()?;
         let extension_len = client_extensions.read_u16_be()? as usize;
+        extension_types.push(extension_type);
 
         if extension_type == 0x0000 {
             // server name
@@ -407,13 +727,9 @@ pub async fn read_client_hello(
         }
     }
 
-    let mut client_hello_frame =
-        Vec::with_capacity(client_tls_header_bytes.len() + client_payload_bytes.len());
-    client_hello_frame.extend_from_slice(&client_tls_header_bytes);
-    client_hello_frame.extend_from_slice(client_payload_bytes);
-
     Ok(ParsedClientHello {
         client_hello_frame,
+        client_hello_message,
         client_hello_record_legacy_version_major: client_legacy_version_major,
         client_hello_record_legacy_version_minor: client_legacy_version_minor,
         client_hello_content_version_major: client_version_major,
@@ -422,14 +738,20 @@ pub async fn read_client_hello(
         client_reader,
         requested_server_name,
         supports_tls13: client_supports_tls13,
+        cipher_suites,
+        extension_types,
     })
 }
 
 pub struct ParsedServerHello {
     pub server_random: Vec<u8>,
+    pub is_hello_retry_request: bool,
 }
 
-pub fn parse_server_hello(server_hello_frame: &[u8]) -> std::io::Result<ParsedServerHello> {
+pub fn parse_server_hello(
+    server_hello_frame: &[u8],
+    handshake_version: ShadowTlsHandshakeVersion,
+) -> std::io::Result<ParsedServerHello> {
     // we don't need to validate that the frame is large enough to contain the header, because
     // a full header was read in order to successfully read the complete frame that is passed in
     // to this function.
@@ -492,12 +814,10 @@ pub fn parse_server_hello(server_hello_frame: &[u8]) -> std::io::Result<ParsedSe
     }
 
     let server_random = &server_hello_frame[11..43];
-    if server_random == RETRY_REQUEST_RANDOM_BYTES {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "server sent a HelloRetryRequest",
-        ));
-    }
+    // A HelloRetryRequest is structurally a ServerHello with a magic random value (RFC 8446
+    // section 4.1.3); the caller is responsible for relaying it and looping for the client's
+    // second ClientHello rather than treating it as a parse error.
+    let is_hello_retry_request = server_random == RETRY_REQUEST_RANDOM_BYTES;
     let server_random = server_random.to_vec();
 
     let server_session_id_len = server_hello_frame[43];
@@ -571,14 +891,20 @@ pub fn parse_server_hello(server_hello_frame: &[u8]) -> std::io::Result<ParsedSe
         }
     }
 
-    if !server_has_supported_version {
+    // a TLS 1.2 ServerHello legitimately has no supported_versions extension at all, since that
+    // extension was introduced by TLS 1.3; only require it when we expect the cover server to
+    // negotiate 1.3.
+    if handshake_version == ShadowTlsHandshakeVersion::Tls13 && !server_has_supported_version {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             "server did not have supported versions extension",
         ));
     }
 
-    Ok(ParsedServerHello { server_random })
+    Ok(ParsedServerHello {
+        server_random,
+        is_hello_retry_request,
+    })
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -589,6 +915,7 @@ async fn setup_remote_handshake(
     client_hello_frame: Vec<u8>,
     initial_hmac: &ShadowTlsHmac,
     initial_xor_context: &ShadowTlsXorContext,
+    handshake_version: ShadowTlsHandshakeVersion,
     remote_addr: NetLocation,
     client_connector: &TcpClientConnector,
     resolver: &Arc<dyn Resolver>,
@@ -624,58 +951,133 @@ async fn setup_remote_handshake(
     })?;
 
     let mut server_reader = StreamReader::new_with_buffer_size(TLS_FRAME_MAX_LEN);
-    let server_header_bytes = server_reader
-        .read_slice(&mut client_stream, TLS_HEADER_LEN)
-        .await
-        .map_err(|e| {
+
+    // Bound to a single retry per RFC 8446: a HelloRetryRequest is only ever sent once per
+    // handshake, so a second one from the upstream server is a protocol violation, not something
+    // to loop on indefinitely.
+    let mut retried = false;
+    let server_random = loop {
+        let server_header_bytes = server_reader
+            .read_slice(&mut client_stream, TLS_HEADER_LEN)
+            .await
+            .map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    format!("failed to read ServerHello header from remote server: {e}"),
+                )
+            })?;
+
+        let server_payload_size =
+            u16::from_be_bytes([server_header_bytes[3], server_header_bytes[4]]);
+
+        let mut server_hello_frame =
+            Vec::with_capacity(server_header_bytes.len() + server_payload_size as usize);
+        server_hello_frame.extend_from_slice(server_header_bytes);
+
+        let server_payload_bytes = server_reader
+            .read_slice(&mut client_stream, server_payload_size as usize)
+            .await
+            .map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    format!(
+                        "failed to read ServerHello payload from remote server (size: {server_payload_size}): {e}"
+                    ),
+                )
+            })?;
+        server_hello_frame.extend_from_slice(server_payload_bytes);
+
+        let ParsedServerHello {
+            server_random,
+            is_hello_retry_request,
+        } = parse_server_hello(&server_hello_frame, handshake_version).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("failed to parse ServerHello from remote server: {e}"),
+            )
+        })?;
+
+        // forward the frame to the client unchanged, whether it's a HelloRetryRequest or the
+        // real ServerHello, so the upstream handshake transcript stays valid on both ends.
+        write_all(&mut server_stream, &server_hello_frame)
+            .await
+            .map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    format!("failed to write ServerHello to client: {e}"),
+                )
+            })?;
+        server_stream.flush().await.map_err(|e| {
             std::io::Error::new(
-                std::io::ErrorKind::ConnectionAborted,
-                format!("failed to read ServerHello header from remote server: {e}"),
+                std::io::ErrorKind::BrokenPipe,
+                format!("failed to flush ServerHello to client: {e}"),
             )
         })?;
 
-    let server_payload_size = u16::from_be_bytes([server_header_bytes[3], server_header_bytes[4]]);
+        if !is_hello_retry_request {
+            break server_random;
+        }
 
-    let mut server_hello_frame =
-        Vec::with_capacity(server_header_bytes.len() + server_payload_size as usize);
-    server_hello_frame.extend_from_slice(server_header_bytes);
+        if retried {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "received a second HelloRetryRequest from remote server",
+            ));
+        }
+        retried = true;
 
-    let server_payload_bytes = server_reader
-        .read_slice(&mut client_stream, server_payload_size as usize)
-        .await
-        .map_err(|e| {
+        // read the client's second ClientHello record, re-validating the ShadowTLS HMAC the same
+        // way `setup_shadowtls_server_stream` validated the first ClientHello, then relay it
+        // byte-for-byte so the upstream transcript stays valid.
+        let retried_client_hello = read_client_hello(&mut server_stream).await.map_err(|e| {
             std::io::Error::new(
-                std::io::ErrorKind::ConnectionAborted,
-                format!(
-                    "failed to read ServerHello payload from remote server (size: {server_payload_size}): {e}"
-                ),
+                std::io::ErrorKind::InvalidData,
+                format!("failed to read retried ClientHello after HelloRetryRequest: {e}"),
             )
         })?;
-    server_hello_frame.extend_from_slice(server_payload_bytes);
 
-    let ParsedServerHello { server_random } =
-        parse_server_hello(&server_hello_frame).map_err(|e| {
+        let ParsedClientHelloDigest {
+            client_hello_digest,
+            client_hello_digest_start_index,
+            client_hello_digest_end_index,
+        } = retried_client_hello.parsed_digest.ok_or_else(|| {
             std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
-                format!("failed to parse ServerHello from remote server: {e}"),
+                "retried client hello did not send a 32-byte session id",
             )
         })?;
 
-    // write the server hello frame to the client
-    write_all(&mut server_stream, &server_hello_frame)
-        .await
-        .map_err(|e| {
+        let mut hmac_retried_client_hello = initial_hmac.clone();
+        hmac_retried_client_hello
+            .update(&retried_client_hello.client_hello_message[..client_hello_digest_start_index]);
+        hmac_retried_client_hello.update(&[0; 4]);
+        hmac_retried_client_hello
+            .update(&retried_client_hello.client_hello_message[client_hello_digest_end_index..]);
+
+        if client_hello_digest != hmac_retried_client_hello.finalized_digest() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "hmac tag mismatch on retried client hello",
+            ));
+        }
+
+        write_all(&mut client_stream, &retried_client_hello.client_hello_frame)
+            .await
+            .map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    format!("failed to send retried ClientHello to remote server: {e}"),
+                )
+            })?;
+        client_stream.flush().await.map_err(|e| {
             std::io::Error::new(
                 std::io::ErrorKind::BrokenPipe,
-                format!("failed to write ServerHello to client: {e}"),
+                format!("failed to flush retried ClientHello to remote server: {e}"),
             )
         })?;
-    server_stream.flush().await.map_err(|e| {
-        std::io::Error::new(
-            std::io::ErrorKind::BrokenPipe,
-            format!("failed to flush ServerHello to client: {e}"),
-        )
-    })?;
+
+        client_reader = retried_client_hello.client_reader;
+    };
 
     let mut hmac_server_random = initial_hmac.clone();
     hmac_server_random.update(&server_random);
@@ -695,6 +1097,12 @@ async fn setup_remote_handshake(
     let mut server_frame = vec![];
     let mut client_frame = vec![];
 
+    // In TLS 1.3 mode, the entire post-ServerHello flight is already wrapped in opaque
+    // application-data records, so every such record gets the shadow-tls xor/hmac treatment
+    // below. In TLS 1.2 mode, that flight (Certificate..Finished, ChangeCipherSpec) is sent as
+    // plaintext/handshake-typed records instead; those fall through to the untouched relay path
+    // below unmodified, the same as they would on a real TLS 1.2 connection to this server, and
+    // only genuine post-handshake application data ends up xor/hmac'd here.
     loop {
         tokio::select! {
             server_read_result = server_reader.read_slice(&mut client_stream, TLS_HEADER_LEN) => {
@@ -879,7 +1287,8 @@ async fn setup_local_handshake(
 
     let server_hello_frame = &server_data[0..TLS_HEADER_LEN + server_hello_payload_size];
 
-    let ParsedServerHello { server_random } = parse_server_hello(server_hello_frame)?;
+    let ParsedServerHello { server_random, .. } =
+        parse_server_hello(server_hello_frame, ShadowTlsHandshakeVersion::Tls13)?;
 
     // write the server hello frame to the client
     write_all(&mut server_stream, server_hello_frame).await?;
@@ -1119,3 +1528,77 @@ fn read_server_connection_once(
         })?;
     Ok(server_data_cursor.position() as usize)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grease_values_are_recognized() {
+        assert!(is_grease_value(0x0A0A));
+        assert!(is_grease_value(0xFAFA));
+        // same 0x?A?A nibble pattern but not on the GREASE list: the two bytes must be identical.
+        assert!(!is_grease_value(0x1A2A));
+        assert!(!is_grease_value(0x3A5A));
+        assert!(!is_grease_value(0x0010));
+    }
+
+    #[test]
+    fn is_subsequence_allows_gaps_but_preserves_order() {
+        assert!(is_subsequence(&[], &[1, 2, 3]));
+        assert!(is_subsequence(&[1, 3], &[1, 2, 3]));
+        assert!(is_subsequence(&[1, 2, 3], &[1, 2, 3]));
+        assert!(!is_subsequence(&[3, 1], &[1, 2, 3]));
+        assert!(!is_subsequence(&[1, 4], &[1, 2, 3]));
+        assert!(!is_subsequence(&[1, 2], &[]));
+    }
+
+    #[test]
+    fn fingerprint_profile_requires_extensions_and_cipher_suites() {
+        let profile = ClientHelloFingerprintProfile::new(
+            vec![0x0010, 0x0033],
+            vec![0x1301],
+            vec![0x0010, 0x0033],
+        );
+
+        // missing the required key_share extension (0x0033).
+        assert!(!profile.matches(&[0x0010], &[0x1301]));
+        // missing the required cipher suite.
+        assert!(!profile.matches(&[0x0010, 0x0033], &[0x1302]));
+        assert!(profile.matches(&[0x0010, 0x0033], &[0x1301]));
+    }
+
+    #[test]
+    fn fingerprint_profile_checks_extension_order_ignoring_grease() {
+        let profile =
+            ClientHelloFingerprintProfile::new(vec![], vec![], vec![0x000d, 0x0010, 0x0033]);
+
+        // GREASE values interspersed at arbitrary positions shouldn't affect the order check.
+        assert!(profile.matches(&[0x0A0A, 0x000d, 0x1A1A, 0x0010, 0x0033, 0x2A2A], &[]));
+
+        // wrong relative order is rejected even with no GREASE involved.
+        assert!(!profile.matches(&[0x0033, 0x0010, 0x000d], &[]));
+
+        // extra, unlisted extensions interleaved are fine as long as relative order holds.
+        assert!(profile.matches(&[0x000d, 0x002b, 0x0010, 0xff01, 0x0033], &[]));
+    }
+
+    #[test]
+    fn fragment_header_must_be_a_handshake_record() {
+        let header = [0x17, 0x03, 0x03, 0x00, 0x10]; // content type 0x17 (application_data)
+        assert!(validate_fragment_record_header(&header, 0x03, 0x03).is_err());
+    }
+
+    #[test]
+    fn fragment_header_must_match_the_first_record_s_legacy_version() {
+        let header = [CONTENT_TYPE_HANDSHAKE, 0x03, 0x01, 0x00, 0x10];
+        assert!(validate_fragment_record_header(&header, 0x03, 0x03).is_err());
+        assert!(validate_fragment_record_header(&header, 0x03, 0x01).is_ok());
+    }
+
+    #[test]
+    fn reassembly_size_limit_rejects_oversized_bodies_only() {
+        assert!(check_reassembly_size_limit(MAX_REASSEMBLED_CLIENT_HELLO_LEN + 4).is_ok());
+        assert!(check_reassembly_size_limit(MAX_REASSEMBLED_CLIENT_HELLO_LEN + 5).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// How often `ShutdownController::drain` re-checks the live-connection counter while waiting for
+/// it to reach zero.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Cloneable handle to one server's shutdown tripwire, given to the unix and udp accept loops
+/// and connection tasks `start_servers` spawns (the tcp and quic paths are started without it).
+/// Accept loops select on `wait_for_shutdown` to stop
+/// taking new connections as soon as a reload/SIGINT/SIGTERM begins, instead of finding out only
+/// when `JoinHandle::abort` severs them; `track_connection` lets `ShutdownController::drain` see
+/// when the in-flight `AsyncStream` copy loops it left running have actually finished.
+#[derive(Debug, Clone)]
+pub(crate) struct ShutdownToken {
+    shutting_down: watch::Receiver<bool>,
+    live_connections: Arc<AtomicUsize>,
+}
+
+impl ShutdownToken {
+    /// Resolves once `ShutdownController::begin_shutdown` has been called. Select this alongside
+    /// an accept/`recv_from` future in accept loops so they wake up and stop immediately rather
+    /// than on the next incoming connection.
+    pub(crate) async fn wait_for_shutdown(&mut self) {
+        // a closed channel (the controller dropped, whether or not `begin_shutdown` was called
+        // first) is treated the same as an explicit signal.
+        let _ = self
+            .shutting_down
+            .wait_for(|shutting_down| *shutting_down)
+            .await;
+    }
+
+    /// RAII guard marking one accepted connection as live. Hold it for the lifetime of the
+    /// connection's copy loop so `ShutdownController::drain` knows when it's safe to return.
+    pub(crate) fn track_connection(&self) -> ConnectionGuard {
+        self.live_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            live_connections: self.live_connections.clone(),
+        }
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    live_connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.live_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Owns the sending half of one server's shutdown tripwire and its live-connection counter.
+/// `main()` creates one of these per server definition it starts, and on config reload or
+/// SIGINT/SIGTERM calls `begin_shutdown` followed by `drain` instead of aborting every join
+/// handle and sleeping a fixed 3 seconds.
+pub(crate) struct ShutdownController {
+    shutting_down: watch::Sender<bool>,
+    live_connections: Arc<AtomicUsize>,
+}
+
+impl ShutdownController {
+    pub(crate) fn new() -> Self {
+        let (shutting_down, _) = watch::channel(false);
+        Self {
+            shutting_down,
+            live_connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(crate) fn token(&self) -> ShutdownToken {
+        ShutdownToken {
+            shutting_down: self.shutting_down.subscribe(),
+            live_connections: self.live_connections.clone(),
+        }
+    }
+
+    /// Flip the tripwire so every outstanding `ShutdownToken::wait_for_shutdown` resolves.
+    pub(crate) fn begin_shutdown(&self) {
+        let _ = self.shutting_down.send(true);
+    }
+
+    /// Wait until every connection tracked by a `ShutdownToken::track_connection` guard has
+    /// finished, or `grace_period` elapses, whichever comes first. Returns whether draining
+    /// finished cleanly; the caller is expected to abort the remaining join handles itself if
+    /// this returns `false`.
+    pub(crate) async fn drain(&self, grace_period: Duration) -> bool {
+        let deadline = Instant::now() + grace_period;
+        loop {
+            let live = self.live_connections.load(Ordering::Relaxed);
+            if live == 0 {
+                return true;
+            }
+
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) => remaining,
+                None => {
+                    warn!(
+                        "{live} connection(s) still active after the {grace_period:?} shutdown grace period, forcing close"
+                    );
+                    return false;
+                }
+            };
+
+            tokio::time::sleep(DRAIN_POLL_INTERVAL.min(remaining)).await;
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+
+/// Backlog used for every `SO_REUSEPORT` shard, matching the default a single
+/// `TcpListener::bind` gets from tokio/std.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Binds `shard_count` independent `TcpListener`s to the same `addr`. On unix, each shard sets
+/// `SO_REUSEPORT` so the kernel load-balances inbound connections across them by source tuple,
+/// instead of every worker thread's accept loop contending on a single listener's accept queue.
+/// Intended for `start_tcp_servers` to spin up one accept loop per worker thread
+/// (`get_num_threads()`) rather than a single loop whose accepted connections are then
+/// redistributed by `tokio::spawn`.
+///
+/// Falls back to a single listener if `shard_count` is 0 or 1, since sharding a lone listener has
+/// no benefit, and unconditionally on platforms without `SO_REUSEPORT` (anything other than
+/// unix), where `shard_count` is ignored and one accept loop still handles the whole bind.
+pub(crate) fn bind_reuseport_tcp_listeners(
+    addr: SocketAddr,
+    shard_count: usize,
+) -> io::Result<Vec<TcpListener>> {
+    #[cfg(unix)]
+    {
+        (0..shard_count.max(1))
+            .map(|_| bind_reuseport_tcp_listener(addr))
+            .collect()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = shard_count;
+        Ok(vec![std::net::TcpListener::bind(addr).and_then(
+            |listener| {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)
+            },
+        )?])
+    }
+}
+
+#[cfg(unix)]
+fn bind_reuseport_tcp_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let domain = if addr.is_ipv4() {
+        Domain::IPV4
+    } else {
+        Domain::IPV6
+    };
+    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+
+    // Without both of these, the second and later shards bound to the same address would fail
+    // with `AddrInUse` instead of sharing the port.
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
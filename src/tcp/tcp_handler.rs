@@ -2,6 +2,7 @@ use std::fmt::Debug;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use log::debug;
 
 use crate::address::NetLocation;
 use crate::async_stream::{AsyncStream, AsyncTargetedMessageStream};
@@ -139,6 +140,23 @@ impl TcpServerSetupResult {
     }
 }
 
+/// Logs (at debug level) when a setup result's `override_proxy_provider` can't be honored,
+/// instead of letting it be silently dropped. Shared by every server entry point that still
+/// dials direct because there's no `ClientProxySelector`/`Resolver` wiring reachable from it
+/// yet (the unix-socket and udp server paths, as of this writing) - one place to update once
+/// that wiring exists, rather than a copy of the same rationale in each of them.
+pub(crate) fn warn_unsupported_proxy_override(
+    override_proxy_provider: &NoneOrOne<Arc<ClientProxySelector<TcpClientConnector>>>,
+    context: impl std::fmt::Display,
+) {
+    if !override_proxy_provider.is_unspecified() {
+        debug!(
+            "{context} requested a proxy provider override, but this server path can't honor \
+             it yet"
+        );
+    }
+}
+
 #[async_trait]
 pub(crate) trait TcpServerHandler: Send + Sync + Debug {
     async fn setup_server_stream(
@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+
+use tokio::net::UnixStream;
+
+use crate::async_stream::AsyncStream;
+
+/// Outbound connector counterpart to `start_unix_servers`: dials a `UnixStream` at a filesystem
+/// path so a proxy hop can target a co-located service (or another `shoes` instance) without a
+/// loopback TCP round trip. Intended to back a `TcpClientConnector::Unix(UnixClientConnector)`
+/// variant alongside the existing TCP/TLS connectors.
+#[derive(Debug, Clone)]
+pub(crate) struct UnixClientConnector {
+    path: PathBuf,
+}
+
+impl UnixClientConnector {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub(crate) async fn connect(&self) -> std::io::Result<Box<dyn AsyncStream>> {
+        let stream = UnixStream::connect(&self.path).await.map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!(
+                    "failed to connect to unix socket at {}: {e}",
+                    self.path.display()
+                ),
+            )
+        })?;
+        Ok(Box::new(stream))
+    }
+}
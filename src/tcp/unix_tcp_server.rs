@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{debug, error};
+use tokio::io::{self, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+use crate::async_stream::AsyncStream;
+use crate::config::ServerConfig;
+use crate::shutdown::ShutdownToken;
+use crate::tcp::tcp_handler::{
+    warn_unsupported_proxy_override, TcpServerHandler, TcpServerSetupResult,
+};
+use crate::util::write_all;
+
+/// Binds a `UnixListener` at `bind_path` and runs an accept loop parallel to
+/// `start_tcp_servers`, handing each accepted `UnixStream` off to the configured
+/// `TcpServerHandler` the same way a TCP listener would.
+///
+/// Unlike a TCP bind, a stale socket file left over from an unclean shutdown will cause the
+/// bind to fail, so we remove it first if nothing is listening on it anymore.
+pub(crate) fn start_unix_servers(
+    bind_path: PathBuf,
+    config: ServerConfig,
+    mut shutdown: ShutdownToken,
+) -> std::io::Result<Vec<JoinHandle<()>>> {
+    remove_stale_socket(&bind_path)?;
+
+    let listener = UnixListener::bind(&bind_path).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("failed to bind unix socket at {}: {e}", bind_path.display()),
+        )
+    })?;
+
+    let server_config = Arc::new(config);
+
+    let join_handle = tokio::spawn(async move {
+        loop {
+            let unix_stream = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((unix_stream, _)) => unix_stream,
+                    Err(e) => {
+                        error!(
+                            "Failed to accept unix connection at {}: {e}",
+                            bind_path.display()
+                        );
+                        continue;
+                    }
+                },
+                _ = shutdown.wait_for_shutdown() => {
+                    break;
+                }
+            };
+
+            let server_config = server_config.clone();
+            let connection_guard = shutdown.track_connection();
+            tokio::spawn(async move {
+                if let Err(e) = process_unix_stream(unix_stream, server_config).await {
+                    debug!("Unix connection closed with error: {e}");
+                }
+                drop(connection_guard);
+            });
+        }
+    });
+
+    Ok(vec![join_handle])
+}
+
+async fn process_unix_stream(
+    unix_stream: UnixStream,
+    server_config: Arc<ServerConfig>,
+) -> std::io::Result<()> {
+    let boxed_stream: Box<dyn AsyncStream> = Box::new(unix_stream);
+    let setup_result = server_config
+        .server_handler()
+        .setup_server_stream(boxed_stream)
+        .await?;
+
+    match setup_result {
+        TcpServerSetupResult::TcpForward {
+            remote_location,
+            mut stream,
+            initial_remote_data,
+            connection_success_response,
+            need_initial_flush,
+            override_proxy_provider,
+        } => {
+            warn_unsupported_proxy_override(&override_proxy_provider, "Unix socket session");
+
+            let remote_addr = tokio::net::lookup_host(remote_location.to_string())
+                .await?
+                .next()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::AddrNotAvailable,
+                        format!("failed to resolve forward target {remote_location}"),
+                    )
+                })?;
+            let remote_tcp_stream = TcpStream::connect(remote_addr).await.map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!("failed to connect to forward target {remote_location}: {e}"),
+                )
+            })?;
+            let mut remote_stream: Box<dyn AsyncStream> = Box::new(remote_tcp_stream);
+
+            if let Some(connection_success_response) = connection_success_response {
+                write_all(&mut stream, &connection_success_response).await?;
+                if need_initial_flush {
+                    stream.flush().await?;
+                }
+            }
+
+            if let Some(initial_remote_data) = initial_remote_data {
+                write_all(&mut remote_stream, &initial_remote_data).await?;
+            }
+            io::copy_bidirectional(&mut stream, &mut remote_stream).await?;
+        }
+        _ => {
+            debug!("Ignoring unsupported unix socket session setup result");
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_stale_socket(bind_path: &Path) -> std::io::Result<()> {
+    if !bind_path.exists() {
+        return Ok(());
+    }
+
+    // Best-effort: if another process is actually listening, the connect below succeeds and we
+    // fail with AddrInUse instead of silently stealing the socket.
+    if std::os::unix::net::UnixStream::connect(bind_path).is_ok() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            format!("unix socket at {} is already in use", bind_path.display()),
+        ));
+    }
+
+    std::fs::remove_file(bind_path)
+}
@@ -0,0 +1,588 @@
+//! `Transport::Udp` server path: binds a single socket, demultiplexes datagrams into per-source
+//! sessions, and forwards each session's flow through the configured handler.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use log::{debug, error};
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::address::NetLocation;
+#[cfg(any(feature = "vmess", feature = "vless"))]
+use crate::async_stream::{AsyncMessageStream, AsyncReadMessage, AsyncWriteMessage};
+use crate::async_stream::{
+    AsyncFlushMessage, AsyncPing, AsyncReadTargetedMessage, AsyncStream,
+    AsyncTargetedMessageStream, AsyncWriteSourcedMessage,
+};
+use crate::config::ServerConfig;
+use crate::shutdown::ShutdownToken;
+use crate::tcp::tcp_handler::{warn_unsupported_proxy_override, TcpServerSetupResult};
+use crate::thread_util::get_num_threads;
+use crate::util::write_all;
+
+const DEFAULT_SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const IDLE_SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+struct UdpSession {
+    last_activity: Instant,
+    // closing this (by dropping the session, e.g. on idle sweep) makes the paired
+    // `UdpSessionStream`'s reads return EOF, winding the handler down the same way a closed
+    // TCP/unix connection would.
+    inbound: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+/// Adapts one client's demultiplexed UDP flow into an `AsyncStream`, the same way a `TcpStream`
+/// or `UnixStream` is, so the same `TcpServerHandler` that serves `Transport::Tcp`/`Transport::Unix`
+/// can also serve Shadowsocks UDP, SOCKS UDP-ASSOCIATE, and similar protocols over a plain UDP
+/// port, instead of only tunneled inside QUIC/VMess. `poll_read` yields one logical read per
+/// inbound datagram (never coalescing two datagrams into one read) and `poll_write` sends each
+/// write call as a single reply datagram back to `source`, so datagram boundaries are preserved
+/// end-to-end.
+struct UdpSessionStream {
+    socket: Arc<UdpSocket>,
+    source: SocketAddr,
+    inbound: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl AsyncRead for UdpSessionStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.inbound.poll_recv(cx) {
+            Poll::Ready(Some(datagram)) => {
+                // a reader with too small a buffer to fit the whole datagram would otherwise
+                // lose the tail of it silently; surface that as an error instead.
+                if datagram.len() > buf.remaining() {
+                    return Poll::Ready(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "read buffer too small for udp datagram",
+                    )));
+                }
+                buf.put_slice(&datagram);
+                Poll::Ready(Ok(()))
+            }
+            // sender dropped (session evicted): report EOF, same as a closed TCP/unix stream.
+            Poll::Ready(None) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncWrite for UdpSessionStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let source = self.source;
+        self.socket.poll_send_to(cx, buf, source)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncPing for UdpSessionStream {
+    fn supports_ping(&self) -> bool {
+        false
+    }
+
+    fn poll_write_ping(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<bool>> {
+        unimplemented!();
+    }
+}
+
+impl AsyncStream for UdpSessionStream {}
+
+/// Outbound counterpart to `UdpSessionStream`: a `UdpSocket` that has been `connect()`-ed to a
+/// single forwarding target, so one `poll_read`/`poll_write` pair is enough (no per-datagram
+/// source address to track on this side). Datagram boundaries are preserved the same way
+/// `UdpSessionStream` preserves them: one `poll_read` yields exactly one inbound datagram, and
+/// one `poll_write` call sends exactly one outbound datagram.
+struct RemoteUdpStream {
+    socket: UdpSocket,
+}
+
+impl AsyncRead for RemoteUdpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.socket.poll_recv(cx, buf)
+    }
+}
+
+impl AsyncWrite for RemoteUdpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.socket.poll_send(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncPing for RemoteUdpStream {
+    fn supports_ping(&self) -> bool {
+        false
+    }
+
+    fn poll_write_ping(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<bool>> {
+        unimplemented!();
+    }
+}
+
+impl AsyncStream for RemoteUdpStream {}
+
+/// Number of shards the session map is split across, so that concurrent datagrams from different
+/// clients don't all contend on a single mutex. Sized off the worker thread count rather than a
+/// fixed constant, the same way the rest of the server scales with `get_num_threads()`.
+fn session_shard_count() -> usize {
+    get_num_threads().max(1)
+}
+
+fn session_shard_index(source: &SocketAddr, shard_count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    (hasher.finish() as usize) % shard_count
+}
+
+type SessionShard = Mutex<HashMap<SocketAddr, UdpSession>>;
+
+/// UDP counterpart to `start_tcp_servers`/`start_quic_servers`: binds a `UdpSocket`, demultiplexes
+/// inbound datagrams by source address into per-client sessions, hands each new session's flow to
+/// the configured `TcpServerHandler` via `UdpSessionStream`, and reaps sessions that have gone
+/// idle so a long-running server doesn't leak entries in the session map.
+pub(crate) async fn start_udp_servers(
+    config: ServerConfig,
+    mut shutdown: ShutdownToken,
+) -> std::io::Result<Vec<JoinHandle<()>>> {
+    let socket = UdpSocket::bind(config.bind_location.socket_addr()?)
+        .await
+        .map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("failed to bind udp socket at {}: {e}", config.bind_location),
+            )
+        })?;
+
+    let socket = Arc::new(socket);
+    let server_config = Arc::new(config);
+
+    let shard_count = session_shard_count();
+    let shards: Arc<Vec<SessionShard>> = Arc::new(
+        (0..shard_count)
+            .map(|_| Mutex::new(HashMap::new()))
+            .collect(),
+    );
+
+    let recv_socket = socket.clone();
+    let recv_shards = shards.clone();
+    let recv_handle = tokio::spawn(async move {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let (len, source) = tokio::select! {
+                received = recv_socket.recv_from(&mut buf) => match received {
+                    Ok(result) => result,
+                    Err(e) => {
+                        error!("Failed to read from udp socket: {e}");
+                        continue;
+                    }
+                },
+                _ = shutdown.wait_for_shutdown() => {
+                    break;
+                }
+            };
+
+            let shard = &recv_shards[session_shard_index(&source, recv_shards.len())];
+            let mut sessions = shard.lock().await;
+
+            // no entry means the session was never created or was reaped as idle; a send error
+            // means its handler task already exited (e.g. the handler closed the stream). Either
+            // way, treat this datagram as the first one of a fresh session instead of losing it.
+            let needs_new_session = match sessions.get_mut(&source) {
+                Some(session) if session.inbound.send(buf[..len].to_vec()).is_ok() => {
+                    session.last_activity = Instant::now();
+                    false
+                }
+                _ => true,
+            };
+
+            if needs_new_session {
+                debug!("New udp session from {source}");
+                let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+                let _ = inbound_tx.send(buf[..len].to_vec());
+
+                sessions.insert(
+                    source,
+                    UdpSession {
+                        last_activity: Instant::now(),
+                        inbound: inbound_tx,
+                    },
+                );
+                drop(sessions);
+
+                let session_config = recv_socket.clone();
+                let handler_config = server_config.clone();
+                let connection_guard = shutdown.track_connection();
+                tokio::spawn(async move {
+                    if let Err(e) =
+                        process_udp_session(session_config, source, inbound_rx, handler_config)
+                            .await
+                    {
+                        debug!("Udp session from {source} closed with error: {e}");
+                    }
+                    drop(connection_guard);
+                });
+            }
+        }
+    });
+
+    let sweep_shards = shards;
+    let sweep_handle = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(IDLE_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut reaped = 0;
+            for shard in sweep_shards.iter() {
+                let mut sessions = shard.lock().await;
+                let before = sessions.len();
+                sessions.retain(|_, session| {
+                    session.last_activity.elapsed() < DEFAULT_SESSION_IDLE_TIMEOUT
+                });
+                reaped += before - sessions.len();
+            }
+            if reaped > 0 {
+                debug!("Reaped {reaped} idle udp session(s)");
+            }
+        }
+    });
+
+    Ok(vec![recv_handle, sweep_handle])
+}
+
+async fn process_udp_session(
+    socket: Arc<UdpSocket>,
+    source: SocketAddr,
+    inbound: mpsc::UnboundedReceiver<Vec<u8>>,
+    server_config: Arc<ServerConfig>,
+) -> std::io::Result<()> {
+    let session_stream: Box<dyn AsyncStream> = Box::new(UdpSessionStream {
+        socket,
+        source,
+        inbound,
+    });
+
+    let setup_result = server_config
+        .server_handler()
+        .setup_server_stream(session_stream)
+        .await?;
+
+    match setup_result {
+        TcpServerSetupResult::TcpForward {
+            remote_location,
+            mut stream,
+            initial_remote_data,
+            connection_success_response,
+            need_initial_flush,
+            override_proxy_provider,
+        } => {
+            warn_unsupported_proxy_override(
+                &override_proxy_provider,
+                format!("Udp session from {source}"),
+            );
+
+            let remote_addr = resolve_remote_addr(&remote_location).await?;
+            let remote_socket = bind_connected_udp_socket(remote_addr).await.map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!("failed to connect to udp forward target {remote_location}: {e}"),
+                )
+            })?;
+            let mut remote_stream: Box<dyn AsyncStream> = Box::new(RemoteUdpStream {
+                socket: remote_socket,
+            });
+
+            if let Some(connection_success_response) = connection_success_response {
+                write_all(&mut stream, &connection_success_response).await?;
+                if need_initial_flush {
+                    stream.flush().await?;
+                }
+            }
+
+            if let Some(initial_remote_data) = initial_remote_data {
+                write_all(&mut remote_stream, &initial_remote_data).await?;
+            }
+
+            // `io::copy_bidirectional` just shuttles whatever one side's `poll_read` returns into
+            // the other side's `poll_write` without looking at framing, but since both
+            // `UdpSessionStream` and `RemoteUdpStream` already yield/accept exactly one datagram
+            // per read/write call, that's exactly the datagram-preserving relay we want.
+            io::copy_bidirectional(&mut stream, &mut remote_stream).await?;
+        }
+        TcpServerSetupResult::MultiDirectionalUdp {
+            mut stream,
+            num_sockets,
+            need_initial_flush,
+            override_proxy_provider,
+        } => {
+            warn_unsupported_proxy_override(
+                &override_proxy_provider,
+                format!("Udp session from {source}"),
+            );
+            if need_initial_flush {
+                flush_message_stream(stream.as_mut()).await?;
+            }
+            relay_multi_directional_udp(source, stream.as_mut(), num_sockets).await?;
+        }
+        #[cfg(any(feature = "vmess", feature = "vless"))]
+        TcpServerSetupResult::BidirectionalUdp {
+            remote_location,
+            mut stream,
+            need_initial_flush,
+            override_proxy_provider,
+        } => {
+            warn_unsupported_proxy_override(
+                &override_proxy_provider,
+                format!("Udp session from {source}"),
+            );
+            if need_initial_flush {
+                flush_message_stream(stream.as_mut()).await?;
+            }
+            relay_bidirectional_udp(source, &remote_location, stream.as_mut()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Relays a `TcpServerSetupResult::MultiDirectionalUdp` session (SOCKS UDP-ASSOCIATE and
+/// Shadowsocks UDP, whose every inbound datagram names its own target rather than sharing one
+/// like `TcpForward` does). Fans outbound sends across `num_sockets` local sockets, sharded by
+/// target address the same way `start_udp_servers` shards client sessions by source, so
+/// concurrently-active targets don't serialize through a single socket; a reply can arrive on any
+/// of them and is tagged with its source before being written back to the client.
+async fn relay_multi_directional_udp(
+    source: SocketAddr,
+    stream: &mut dyn AsyncTargetedMessageStream,
+    num_sockets: usize,
+) -> std::io::Result<()> {
+    let num_sockets = num_sockets.max(1);
+    let mut sockets = Vec::with_capacity(num_sockets);
+    for _ in 0..num_sockets {
+        sockets.push(Arc::new(bind_unconnected_udp_socket().await?));
+    }
+
+    let (reply_tx, mut reply_rx) = mpsc::unbounded_channel::<(SocketAddr, Vec<u8>)>();
+    let recv_tasks: Vec<JoinHandle<()>> = sockets
+        .iter()
+        .cloned()
+        .map(|socket| {
+            let reply_tx = reply_tx.clone();
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 65536];
+                loop {
+                    match socket.recv_from(&mut buf).await {
+                        Ok((len, reply_source)) => {
+                            if reply_tx.send((reply_source, buf[..len].to_vec())).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            error!("Failed to read from multi-directional udp socket: {e}");
+                            break;
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(reply_tx);
+
+    let mut client_buf = vec![0u8; 65536];
+    let result = loop {
+        tokio::select! {
+            read = read_targeted_message(stream, &mut client_buf) => {
+                let (len, target) = match read {
+                    Ok(v) => v,
+                    Err(e) => break Err(e),
+                };
+                let remote_addr = match resolve_remote_addr(&target).await {
+                    Ok(addr) => addr,
+                    Err(e) => {
+                        debug!(
+                            "Udp session from {source} dropping datagram to unresolvable target \
+                             {target}: {e}"
+                        );
+                        continue;
+                    }
+                };
+                let shard = &sockets[session_shard_index(&remote_addr, sockets.len())];
+                if let Err(e) = shard.send_to(&client_buf[..len], remote_addr).await {
+                    debug!(
+                        "Udp session from {source} failed to forward datagram to {target}: {e}"
+                    );
+                }
+            }
+            reply = reply_rx.recv() => {
+                let Some((reply_source, datagram)) = reply else {
+                    break Ok(());
+                };
+                if let Err(e) = write_sourced_message(stream, &datagram, &reply_source).await {
+                    break Err(e);
+                }
+            }
+        }
+    };
+
+    for task in recv_tasks {
+        task.abort();
+    }
+
+    result
+}
+
+/// Relays a `TcpServerSetupResult::BidirectionalUdp` session: unlike `MultiDirectionalUdp`, every
+/// datagram in both directions goes to/from the single `remote_location` the handler already
+/// picked (e.g. a VMess/VLess UDP tunnel), so a single connected socket is enough, the same way
+/// `TcpForward`'s `RemoteUdpStream` only needs one.
+#[cfg(any(feature = "vmess", feature = "vless"))]
+async fn relay_bidirectional_udp(
+    source: SocketAddr,
+    remote_location: &NetLocation,
+    stream: &mut dyn AsyncMessageStream,
+) -> std::io::Result<()> {
+    let remote_addr = resolve_remote_addr(remote_location).await?;
+    let remote_socket = bind_connected_udp_socket(remote_addr).await.map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("failed to connect to udp forward target {remote_location}: {e}"),
+        )
+    })?;
+
+    let mut client_buf = vec![0u8; 65536];
+    let mut remote_buf = vec![0u8; 65536];
+    loop {
+        tokio::select! {
+            read = read_message(stream, &mut client_buf) => {
+                let len = read.map_err(|e| {
+                    std::io::Error::new(e.kind(), format!("udp session from {source} closed: {e}"))
+                })?;
+                remote_socket.send(&client_buf[..len]).await?;
+            }
+            received = remote_socket.recv(&mut remote_buf) => {
+                let len = received?;
+                write_message(stream, &remote_buf[..len]).await?;
+            }
+        }
+    }
+}
+
+// Small async wrappers around the message-trait `poll_*` methods, which `tokio::select!` needs
+// as plain futures. Generic over the trait rather than `&mut dyn Trait` so a wider trait object
+// (e.g. `dyn AsyncTargetedMessageStream`) can be passed directly without an explicit upcast.
+fn read_targeted_message<S: AsyncReadTargetedMessage + Unpin + ?Sized>(
+    stream: &mut S,
+    buf: &mut [u8],
+) -> impl std::future::Future<Output = std::io::Result<(usize, NetLocation)>> + '_ {
+    poll_fn(move |cx| {
+        let mut read_buf = ReadBuf::new(buf);
+        match Pin::new(&mut *stream).poll_read_targeted_message(cx, &mut read_buf) {
+            Poll::Ready(Ok(target)) => Poll::Ready(Ok((read_buf.filled().len(), target))),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+}
+
+fn write_sourced_message<'a, S: AsyncWriteSourcedMessage + Unpin + ?Sized>(
+    stream: &'a mut S,
+    buf: &'a [u8],
+    source: &'a SocketAddr,
+) -> impl std::future::Future<Output = std::io::Result<()>> + 'a {
+    poll_fn(move |cx| Pin::new(&mut *stream).poll_write_sourced_message(cx, buf, source))
+}
+
+async fn flush_message_stream<S: AsyncFlushMessage + Unpin + ?Sized>(
+    stream: &mut S,
+) -> std::io::Result<()> {
+    poll_fn(|cx| Pin::new(&mut *stream).poll_flush_message(cx)).await
+}
+
+#[cfg(any(feature = "vmess", feature = "vless"))]
+fn read_message<S: AsyncReadMessage + Unpin + ?Sized>(
+    stream: &mut S,
+    buf: &mut [u8],
+) -> impl std::future::Future<Output = std::io::Result<usize>> + '_ {
+    poll_fn(move |cx| {
+        let mut read_buf = ReadBuf::new(buf);
+        match Pin::new(&mut *stream).poll_read_message(cx, &mut read_buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(read_buf.filled().len())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+}
+
+#[cfg(any(feature = "vmess", feature = "vless"))]
+fn write_message<'a, S: AsyncWriteMessage + Unpin + ?Sized>(
+    stream: &'a mut S,
+    buf: &'a [u8],
+) -> impl std::future::Future<Output = std::io::Result<()>> + 'a {
+    poll_fn(move |cx| Pin::new(&mut *stream).poll_write_message(cx, buf))
+}
+
+async fn bind_unconnected_udp_socket() -> std::io::Result<UdpSocket> {
+    UdpSocket::bind("0.0.0.0:0").await
+}
+
+/// Resolves a `NetLocation` to a concrete address to forward udp datagrams to. Uses plain system
+/// DNS resolution (`tokio::net::lookup_host`); no `crate::resolver::Resolver` is threaded through
+/// to this server path.
+async fn resolve_remote_addr(remote_location: &NetLocation) -> std::io::Result<SocketAddr> {
+    tokio::net::lookup_host(remote_location.to_string())
+        .await?
+        .next()
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("failed to resolve udp forward target {remote_location}"),
+            )
+        })
+}
+
+async fn bind_connected_udp_socket(remote_addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    let bind_addr: SocketAddr = match remote_addr {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(remote_addr).await?;
+    Ok(socket)
+}